@@ -0,0 +1,288 @@
+// コマンドライン引数の解析
+
+use crate::integrator::*;
+use crate::rayt::camera::*;
+use crate::rayt::hdr::*;
+use crate::rayt::render::*;
+use crate::rayt::sampler::*;
+use crate::rayt::tonemap::*;
+use clap::{value_t, App, Arg};
+
+pub struct Args {
+    pub scene: String, // 組み込みシーンの名前かシーンファイルのパス
+    pub threads: Option<usize>,
+    pub preview: bool, // プレビューウィンドウを開く
+    pub integrator: IntegratorKind,
+    pub roulette_depth: usize, // ロシアンルーレットを始める反射回数
+    pub stats: bool,           // 経路の統計を表示する
+    pub config: RenderConfig,
+}
+
+impl Args {
+    // scenes は --help に表示する組み込みシーンの (名前, 説明)
+    pub fn parse(scenes: &[(&str, &str)], default_scene: &str) -> Self {
+        let scene_list = scenes
+            .iter()
+            .map(|(name, about)| format!("    {:<16}{}", name, about))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let after_help = format!("BUILT-IN SCENES:\n{}", scene_list);
+        let default_depth = MAX_RAY_BOUNCE_DEPTH.to_string();
+        let default_roulette_depth = ROULETTE_MIN_DEPTH.to_string();
+        let tonemap_help = format!("Tone mapping operator: {}", ToneMap::NAMES.join(", "));
+        let sampler_help = format!("Sample sequence: {}", SamplerKind::NAMES.join(", "));
+        let projection_help = format!(
+            "Camera projection (overrides the scene): {}",
+            Projection::NAMES.join(", ")
+        );
+        let integrator_help = format!(
+            "Light transport algorithm: {}",
+            IntegratorKind::NAMES.join(", ")
+        );
+
+        let app = App::new(env!("CARGO_PKG_NAME"))
+            .version(env!("CARGO_PKG_VERSION"))
+            .about("A small path tracer")
+            .after_help(after_help.as_str())
+            .arg(
+                Arg::with_name("scene")
+                    .help("Built-in scene name or path to a JSON scene file")
+                    .default_value(default_scene)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("width")
+                    .long("width")
+                    .short("W")
+                    .value_name("PIXELS")
+                    .help("Image width (overrides the scene)")
+                    .validator(image_size)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("height")
+                    .long("height")
+                    .short("H")
+                    .value_name("PIXELS")
+                    .help("Image height (overrides the scene)")
+                    .validator(image_size)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("spp")
+                    .long("spp")
+                    .short("s")
+                    .value_name("N")
+                    .help("Samples per pixel (overrides the scene)")
+                    .validator(positive)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("projection")
+                    .long("projection")
+                    .value_name("KIND")
+                    .help(&projection_help)
+                    .validator(|s| s.parse::<Projection>().map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("depth")
+                    .long("depth")
+                    .short("d")
+                    .value_name("N")
+                    .help("Maximum ray bounce depth (a safety net for Russian roulette)")
+                    .default_value(&default_depth)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rr-depth")
+                    .long("rr-depth")
+                    .value_name("N")
+                    .help("Bounces before Russian roulette may terminate a path")
+                    .default_value(&default_roulette_depth)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("gamma")
+                    .long("gamma")
+                    .short("g")
+                    .value_name("FACTOR")
+                    .help("Use a pure power curve instead of the sRGB transfer function")
+                    .validator(positive_float)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("exposure")
+                    .long("exposure")
+                    .short("e")
+                    .value_name("EV")
+                    .help("Exposure compensation in stops")
+                    .default_value("0")
+                    .allow_hyphen_values(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("tonemap")
+                    .long("tonemap")
+                    .short("t")
+                    .value_name("OPERATOR")
+                    .help(&tonemap_help)
+                    .default_value("clamp")
+                    .validator(|s| s.parse::<ToneMap>().map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .value_name("FILE")
+                    .help("Output image path (.hdr/.pfm keep the unclamped linear values)")
+                    .default_value(OUTPUT_FILENAME)
+                    .validator(output_path)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("hdr")
+                    .long("hdr")
+                    .value_name("FILE")
+                    .help("Also write the linear framebuffer to FILE (.hdr or .pfm)")
+                    .validator(hdr_path)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("integrator")
+                    .long("integrator")
+                    .short("i")
+                    .value_name("KIND")
+                    .help(&integrator_help)
+                    .default_value("path")
+                    .validator(|s| s.parse::<IntegratorKind>().map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("sampler")
+                    .long("sampler")
+                    .value_name("KIND")
+                    .help(&sampler_help)
+                    .default_value("independent")
+                    .validator(|s| s.parse::<SamplerKind>().map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .value_name("N")
+                    .help("Random seed (renders with the same seed are identical)")
+                    .default_value("0")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("threads")
+                    .long("threads")
+                    .short("j")
+                    .value_name("N")
+                    .help("Number of render threads (default: number of CPUs)")
+                    .validator(positive)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("preview")
+                    .long("preview")
+                    .short("p")
+                    .help("Show the image in a window while rendering (S: save, Esc/Q: quit)"),
+            )
+            .arg(
+                Arg::with_name("stats")
+                    .long("stats")
+                    .help("Print path statistics (bounces, termination reasons) after rendering"),
+            )
+            .arg(
+                Arg::with_name("no-backup")
+                    .long("no-backup")
+                    .help("Overwrite the output file instead of renaming it to *_bak"),
+            );
+        let matches = app.get_matches();
+
+        let optional = |name| {
+            if matches.is_present(name) {
+                Some(value_t!(matches, name, usize).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            }
+        };
+
+        Self {
+            scene: matches.value_of("scene").unwrap().to_string(),
+            threads: optional("threads"),
+            preview: matches.is_present("preview"),
+            integrator: value_t!(matches, "integrator", IntegratorKind)
+                .unwrap_or_else(|e| e.exit()),
+            roulette_depth: value_t!(matches, "rr-depth", usize).unwrap_or_else(|e| e.exit()),
+            stats: matches.is_present("stats"),
+            config: RenderConfig {
+                width: optional("width").map(|x| x as u32),
+                height: optional("height").map(|x| x as u32),
+                spp: optional("spp"),
+                max_depth: value_t!(matches, "depth", usize).unwrap_or_else(|e| e.exit()),
+                exposure: value_t!(matches, "exposure", f64).unwrap_or_else(|e| e.exit()),
+                tonemap: value_t!(matches, "tonemap", ToneMap).unwrap_or_else(|e| e.exit()),
+                gamma: if matches.is_present("gamma") {
+                    Some(value_t!(matches, "gamma", f64).unwrap_or_else(|e| e.exit()))
+                } else {
+                    None
+                },
+                output: matches.value_of("output").unwrap().to_string(),
+                hdr_output: matches.value_of("hdr").map(String::from),
+                backup: !matches.is_present("no-backup"),
+                seed: value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit()),
+                sampler: value_t!(matches, "sampler", SamplerKind).unwrap_or_else(|e| e.exit()),
+                projection: if matches.is_present("projection") {
+                    Some(value_t!(matches, "projection", Projection).unwrap_or_else(|e| e.exit()))
+                } else {
+                    None
+                },
+            },
+        }
+    }
+}
+
+fn positive(s: String) -> Result<(), String> {
+    match s.parse::<usize>() {
+        Ok(x) if x > 0 => Ok(()),
+        _ => Err(format!("expected a positive integer, found {:?}", s)),
+    }
+}
+
+// 画素の位置を (n - 1) で割るので 2 以上
+fn image_size(s: String) -> Result<(), String> {
+    match s.parse::<usize>() {
+        Ok(x) if x >= 2 => Ok(()),
+        _ => Err(format!("expected an integer of at least 2, found {:?}", s)),
+    }
+}
+
+fn positive_float(s: String) -> Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(()),
+        _ => Err(format!("expected a positive number, found {:?}", s)),
+    }
+}
+
+fn output_path(s: String) -> Result<(), String> {
+    if is_output_path(&s) {
+        Ok(())
+    } else {
+        Err(format!(
+            "expected a .png, .jpg, .bmp, .tiff, .tga, .ppm, .hdr or .pfm file, found {:?}",
+            s
+        ))
+    }
+}
+
+fn hdr_path(s: String) -> Result<(), String> {
+    if is_hdr_path(&s) {
+        Ok(())
+    } else {
+        Err(format!("expected a .hdr or .pfm file, found {:?}", s))
+    }
+}
@@ -0,0 +1,175 @@
+// 正距円筒図法の HDR 画像による環境光
+//
+// 画像の中心が -z、右端に向かって +x を通り一周する (全天球カメラで描いた画像と同じ向き)。
+// 明るい方向ほど多く選ばれるように、画素の輝度に比例した2次元の分布でサンプリングする。
+
+use crate::rayt::distribution::*;
+use crate::rayt::hdr::*;
+use crate::*;
+
+// 画像と、方向を選ぶための分布 (回転や明るさの違う光源で共有できる)
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>, // リニア空間の放射輝度 (左上から行順)
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn load(path: impl AsRef<std::path::Path>) -> image::ImageResult<Self> {
+        let (width, height, pixels) = load_image(path)?;
+        Ok(Self::new(width as usize, height as usize, pixels))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        // 極に近い行ほど立体角が小さいので sinθ をかける
+        let func = pixels
+            .chunks(width)
+            .enumerate()
+            .flat_map(|(y, row)| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                row.iter().map(move |c| c.luminance().max(0.0) * sin_theta)
+            })
+            .collect::<Vec<_>>();
+        Self {
+            width,
+            height,
+            distribution: Distribution2D::new(&func, width, height),
+            pixels,
+        }
+    }
+
+    // 方向 -> 画像上の位置 [u, v] ∈ [0, 1]² (v は上から)
+    fn uv(d: Vec3) -> [f64; 2] {
+        let d = d.normalize();
+        let phi = d.x().atan2(-d.z());
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        [phi / PI2 + 0.5, theta * FRAC_1_PI]
+    }
+
+    // 画像上の位置 -> (方向, sinθ)
+    fn direction([u, v]: [f64; 2]) -> (Vec3, f64) {
+        let (sin_phi, cos_phi) = ((u - 0.5) * PI2).sin_cos();
+        let (sin_theta, cos_theta) = (v * PI).sin_cos();
+        (
+            Vec3::new(sin_theta * sin_phi, cos_theta, -sin_theta * cos_phi),
+            sin_theta,
+        )
+    }
+
+    fn lookup(&self, [u, v]: [f64; 2]) -> Color {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+// 無限遠にある光源として光源リストに入れる (シーンの形状には入れない)
+#[derive(Clone)]
+pub struct EnvironmentLight {
+    map: Arc<EnvironmentMap>,
+    rotation: Quat,
+    scale: f64, // 放射輝度の倍率
+}
+
+impl EnvironmentLight {
+    pub fn new(map: Arc<EnvironmentMap>) -> Self {
+        Self {
+            map,
+            rotation: Quat::unit(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn rotate(mut self, axis: Vec3, angle: f64) -> Self {
+        self.rotation = Quat::from_rot(axis, angle.to_radians()) * self.rotation;
+        self
+    }
+
+    pub fn scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    // 方向 d (ワールド座標) から届く放射輝度
+    pub fn radiance(&self, d: Vec3) -> Color {
+        let local = self.rotation.conj().rotate(d);
+        self.map.lookup(EnvironmentMap::uv(local)) * self.scale
+    }
+}
+
+impl Shape for EnvironmentLight {
+    fn hit(&self, _ray: &Ray, _t0: f64, _t1: f64) -> Option<HitInfo> {
+        None
+    }
+
+    fn bounding_box(&self) -> AABB {
+        AABB::empty()
+    }
+
+    // 立体角あたりの確率密度 (画像上の密度を球面に移す)
+    fn pdf_value(&self, _o: Vec3, v: Vec3) -> f64 {
+        let local = self.rotation.conj().rotate(v);
+        let uv = EnvironmentMap::uv(local);
+        let (_, sin_theta) = EnvironmentMap::direction(uv);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.map.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random(&self, _o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (uv, _) = self.map.distribution.sample(sampler.next_2d());
+        let (d, _) = EnvironmentMap::direction(uv);
+        self.rotation.rotate(d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv() {
+        for &uv in &[[0.5, 0.5], [0.75, 0.25], [0.1, 0.9]] {
+            let (d, _) = EnvironmentMap::direction(uv);
+            let [u, v] = EnvironmentMap::uv(d);
+            assert!((u - uv[0]).abs() < 1e-12 && (v - uv[1]).abs() < 1e-12);
+        }
+        // 画像の中心は -z、右に 1/4 進むと +x
+        assert!((EnvironmentMap::direction([0.5, 0.5]).0 + Vec3::zaxis()).near_zero());
+        assert!((EnvironmentMap::direction([0.75, 0.5]).0 - Vec3::xaxis()).near_zero());
+    }
+
+    // 明るい画素の方向ばかり選ばれ、pdf は球面全体で積分すると 1 になる
+    #[test]
+    fn test_sampling() {
+        let (width, height) = (8, 4);
+        let mut pixels = vec![Color::full(0.01); width * height];
+        pixels[width + 6] = Color::full(100.0);
+        let light = EnvironmentLight::new(Arc::new(EnvironmentMap::new(width, height, pixels)))
+            .rotate(Vec3::yaxis(), 30.0);
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let bright = light.radiance(
+            light
+                .rotation
+                .rotate(EnvironmentMap::direction([0.8, 0.4]).0),
+        );
+        assert_eq!(Color::full(100.0), bright);
+
+        let n = 4096;
+        let mut hits = 0;
+        let mut integral = 0.0;
+        for _ in 0..n {
+            let d = light.random(Point3::zero(), sampler.as_mut());
+            if light.radiance(d) == bright {
+                hits += 1;
+            }
+            // 一様な方向で pdf を積分する
+            let u = Vec3::random_in_unit_sphere(sampler.as_mut());
+            integral += light.pdf_value(Point3::zero(), u) * 4.0 * PI;
+        }
+        assert!(hits > n * 9 / 10);
+        assert!((integral / n as f64 - 1.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,879 @@
+// シーンの内容と、それをどう描画するか (積分器) を分ける
+//
+// シーンは形状・光源・背景・カメラだけを記述し、
+// 光線の追跡は Integrator の実装が受け持つ。
+
+use crate::*;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// 描画するシーンの内容
+pub trait Scene: Sync {
+    // カメラの置き方
+    fn view(&self) -> View;
+    fn world(&self) -> &dyn Shape;
+    // 重点的にサンプリングする光源
+    fn light(&self) -> Option<&Arc<dyn Shape>> {
+        None
+    }
+    fn background(&self, _d: Vec3) -> Color {
+        Color::zero()
+    }
+    fn width(&self) -> u32 {
+        IMAGE_WIDTH
+    }
+    fn height(&self) -> u32 {
+        IMAGE_HEIGHT
+    }
+    fn spp(&self) -> usize {
+        SAMPLES_PER_PIXEL
+    }
+}
+
+pub trait Integrator: Send + Sync {
+    // ray の方向から届く放射輝度
+    fn trace(&self, scene: &dyn Scene, ray: Ray, depth: usize, sampler: &mut dyn Sampler) -> Color;
+
+    // 記録した経路の統計 (記録しない積分器は None)
+    fn statistics(&self) -> Option<&PathStatistics> {
+        None
+    }
+}
+
+// 経路が終わった理由
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
+    Escaped,  // 何にも当たらず背景に抜けた
+    Absorbed, // 散乱しなかった (光源・吸収・pdf が 0 の方向)
+    Roulette, // ロシアンルーレットで打ち切った
+    MaxDepth, // 反射回数の上限に達した
+}
+
+impl Termination {
+    const ALL: [Termination; 4] = [
+        Termination::Escaped,
+        Termination::Absorbed,
+        Termination::Roulette,
+        Termination::MaxDepth,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Termination::Escaped => "escaped",
+            Termination::Absorbed => "absorbed",
+            Termination::Roulette => "roulette",
+            Termination::MaxDepth => "max depth",
+        }
+    }
+}
+
+// 1本の経路の統計
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathStats {
+    pub bounces: usize, // 追跡した反射の回数
+    pub termination: Termination,
+}
+
+impl PathStats {
+    const fn new() -> Self {
+        Self {
+            bounces: 0,
+            termination: Termination::MaxDepth,
+        }
+    }
+
+    // bounce 回目の衝突で経路が終わったことを記録する
+    fn end(&mut self, bounce: usize, termination: Termination) {
+        self.bounces = bounce;
+        self.termination = termination;
+    }
+}
+
+// 統計を記録する積分器なら記録して、放射輝度を返す
+fn record(statistics: &Option<PathStatistics>, (radiance, stats): (Color, PathStats)) -> Color {
+    if let Some(statistics) = statistics {
+        statistics.record(stats);
+    }
+    radiance
+}
+
+// 経路の統計の集計 (複数スレッドから記録される)
+#[derive(Debug, Default)]
+pub struct PathStatistics {
+    paths: AtomicU64,
+    bounces: AtomicU64,
+    max_bounces: AtomicUsize,
+    terminations: [AtomicU64; 4], // Termination::ALL の順
+}
+
+impl PathStatistics {
+    pub fn record(&self, stats: PathStats) {
+        self.paths.fetch_add(1, Ordering::Relaxed);
+        self.bounces
+            .fetch_add(stats.bounces as u64, Ordering::Relaxed);
+        self.max_bounces.fetch_max(stats.bounces, Ordering::Relaxed);
+        let index = Termination::ALL
+            .iter()
+            .position(|t| *t == stats.termination)
+            .unwrap();
+        self.terminations[index].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for PathStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths = self.paths.load(Ordering::Relaxed);
+        let ratio = |n: u64| n as f64 / paths.max(1) as f64;
+        write!(
+            f,
+            "paths: {}, mean bounces: {:.2}, max bounces: {}",
+            paths,
+            ratio(self.bounces.load(Ordering::Relaxed)),
+            self.max_bounces.load(Ordering::Relaxed)
+        )?;
+        for (t, count) in Termination::ALL.iter().zip(self.terminations.iter()) {
+            let count = count.load(Ordering::Relaxed);
+            write!(f, "\n  {:<10}{:>6.2}%", t.name(), 100.0 * ratio(count))?;
+        }
+        Ok(())
+    }
+}
+
+// ロシアンルーレットを始めるまでの反射回数の既定値
+pub const ROULETTE_MIN_DEPTH: usize = 3;
+
+// ロシアンルーレット
+// min_depth 回反射した後は、スループットに応じた確率で経路を打ち切る。
+// 生き残った経路の重みを 1 / 確率 倍するので期待値は変わらない
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RussianRoulette {
+    min_depth: usize,
+}
+
+impl RussianRoulette {
+    pub const fn new(min_depth: usize) -> Self {
+        Self { min_depth }
+    }
+
+    // bounce 回目の反射の後で経路を続けるなら、スループットに掛ける係数を返す
+    pub fn survive(
+        &self,
+        bounce: usize,
+        throughput: Color,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f64> {
+        if bounce < self.min_depth {
+            return Some(1.0);
+        }
+        let p = throughput
+            .iter()
+            .fold(0.0, |acc: f64, x| acc.max(*x))
+            .min(1.0);
+        if sampler.next_f64() < p {
+            Some(p.recip())
+        } else {
+            None
+        }
+    }
+}
+
+// 材質の pdf だけでサンプリングするパストレーサー
+pub struct PathTracer {
+    roulette: RussianRoulette,
+    statistics: Option<PathStatistics>,
+}
+
+impl PathTracer {
+    pub fn new(roulette: RussianRoulette, statistics: bool) -> Self {
+        Self {
+            roulette,
+            statistics: statistics.then(PathStatistics::default),
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn trace(&self, scene: &dyn Scene, ray: Ray, depth: usize, sampler: &mut dyn Sampler) -> Color {
+        let path = trace_path(scene, None, self.roulette, ray, depth, sampler);
+        record(&self.statistics, path)
+    }
+
+    fn statistics(&self) -> Option<&PathStatistics> {
+        self.statistics.as_ref()
+    }
+}
+
+// 光源と材質の pdf を半々に混ぜてサンプリングするパストレーサー
+pub struct LightPathTracer {
+    roulette: RussianRoulette,
+    statistics: Option<PathStatistics>,
+}
+
+impl LightPathTracer {
+    pub fn new(roulette: RussianRoulette, statistics: bool) -> Self {
+        Self {
+            roulette,
+            statistics: statistics.then(PathStatistics::default),
+        }
+    }
+}
+
+impl Integrator for LightPathTracer {
+    fn trace(&self, scene: &dyn Scene, ray: Ray, depth: usize, sampler: &mut dyn Sampler) -> Color {
+        let path = trace_path(scene, scene.light(), self.roulette, ray, depth, sampler);
+        record(&self.statistics, path)
+    }
+
+    fn statistics(&self) -> Option<&PathStatistics> {
+        self.statistics.as_ref()
+    }
+}
+
+// light が None の場合は材質の pdf だけでサンプリングする
+// depth は打ち切られなかった経路の反射回数の上限
+fn trace_path(
+    scene: &dyn Scene,
+    light: Option<&Arc<dyn Shape>>,
+    roulette: RussianRoulette,
+    ray: Ray,
+    depth: usize,
+    sampler: &mut dyn Sampler,
+) -> (Color, PathStats) {
+    let mut ray = ray;
+    let mut radiance = Color::zero();
+    let mut throughput = Color::one();
+    let mut stats = PathStats::new();
+
+    for bounce in 0..=depth {
+        ray = seed_ray(ray, sampler);
+        let hit = match scene.world().hit(&ray, 0.001, f64::MAX) {
+            Some(hit) => hit,
+            None => {
+                radiance += throughput * scene.background(ray.direction);
+                stats.end(bounce, Termination::Escaped);
+                break;
+            }
+        };
+        radiance += throughput * hit.m.emitted(&ray, &hit);
+
+        if bounce == depth {
+            stats.end(bounce, Termination::MaxDepth);
+            break;
+        }
+        let lobes = hit.m.lobes(&hit);
+        if lobes.is_empty() {
+            stats.end(bounce, Termination::Absorbed);
+            break;
+        }
+        let frame = hit.frame();
+        let wo = frame.project(-ray.direction.normalize());
+
+        let direction = match light {
+            // 光源と材質の pdf を半々に混ぜる (鏡面は材質の向きしかありえない)
+            Some(light) if !lobes.is_specular() => {
+                let direction = if sampler.next_f64() < 0.5 {
+                    Some(light.random(hit.p, sampler))
+                } else {
+                    hit.m
+                        .sample(&hit, wo, bsdf_random(sampler))
+                        .map(|sample| frame.local(sample.wi))
+                };
+                direction.and_then(|direction| {
+                    let wi = frame.project(direction.normalize());
+                    let pdf =
+                        0.5 * light.pdf_value(hit.p, direction) + 0.5 * hit.m.pdf(&hit, wi, wo);
+                    if pdf <= 0.0 {
+                        return None;
+                    }
+                    throughput = throughput * hit.m.eval(&hit, wi, wo) / pdf;
+                    Some(direction)
+                })
+            }
+            _ => hit.m.sample(&hit, wo, bsdf_random(sampler)).map(|sample| {
+                throughput = throughput * sample.weight;
+                frame.local(sample.wi)
+            }),
+        };
+        match direction {
+            Some(direction) if !hit.leaks(direction) => {
+                ray = Ray::with_time(hit.p, direction, ray.time)
+            }
+            _ => {
+                stats.end(bounce, Termination::Absorbed);
+                break;
+            }
+        }
+
+        match roulette.survive(bounce, throughput, sampler) {
+            Some(weight) => throughput *= weight,
+            None => {
+                stats.end(bounce, Termination::Roulette);
+                break;
+            }
+        }
+    }
+    (radiance, stats)
+}
+
+// 媒質の中で散乱するまでの距離に使う乱数の種を、サンプラーから取って光線に持たせる
+fn seed_ray(ray: Ray, sampler: &mut dyn Sampler) -> Ray {
+    ray.with_seed(sampler.next_f64().to_bits())
+}
+
+// 材質のサンプリングに使う乱数 (成分の選択と向き)
+fn bsdf_random(sampler: &mut dyn Sampler) -> [f64; 3] {
+    let u0 = sampler.next_f64();
+    let [u1, u2] = sampler.next_2d();
+    [u0, u1, u2]
+}
+
+// MIS の重み付け
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    Power, // β = 2
+}
+
+impl MisHeuristic {
+    // pdf で取ったサンプルに対する重み (other はもう一方の手法の pdf)
+    pub fn weight(&self, pdf: f64, other: f64) -> f64 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other),
+            MisHeuristic::Power => (pdf * pdf, other * other),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+// 次イベント推定つきのパストレーサー
+// 衝突ごとに光源と材質の pdf から1方向ずつサンプリングし、MIS で重み付けして足し合わせる
+pub struct MisPathTracer {
+    heuristic: MisHeuristic,
+    roulette: RussianRoulette,
+    statistics: Option<PathStatistics>,
+}
+
+impl MisPathTracer {
+    pub fn new(heuristic: MisHeuristic, roulette: RussianRoulette, statistics: bool) -> Self {
+        Self {
+            heuristic,
+            roulette,
+            statistics: statistics.then(PathStatistics::default),
+        }
+    }
+
+    // 光源を1回サンプリングした寄与 (重み込み、光源がなければ 0)
+    fn sample_light(
+        &self,
+        scene: &dyn Scene,
+        ray: &Ray,
+        hit: &HitInfo,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let light = match scene.light() {
+            Some(light) => light,
+            None => return Color::zero(),
+        };
+        let light_ray = Ray::with_time(hit.p, light.random(hit.p, sampler), ray.time);
+        let light_ray = seed_ray(light_ray, sampler);
+        let light_pdf = light.pdf_value(hit.p, light_ray.direction);
+        if light_pdf <= 0.0 || hit.leaks(light_ray.direction) {
+            return Color::zero();
+        }
+        let frame = hit.frame();
+        let wo = frame.project(-ray.direction.normalize());
+        let wi = frame.project(light_ray.direction.normalize());
+        let f = hit.m.eval(hit, wi, wo);
+        if f.near_zero() {
+            return Color::zero();
+        }
+        // 最初に当たった物体の放射輝度 (遮られていれば光源以外に当たる)
+        // 何にも当たらなければ背景 (環境光) の放射輝度
+        let emitted = match scene.world().hit(&light_ray, 0.001, f64::MAX) {
+            Some(light_hit) => light_hit.m.emitted(&light_ray, &light_hit),
+            None => scene.background(light_ray.direction),
+        };
+        let weight = self.heuristic.weight(light_pdf, hit.m.pdf(hit, wi, wo));
+        f * emitted * (weight / light_pdf)
+    }
+
+    // 材質の pdf でサンプリングした光線が光源に届いた場合の重み
+    fn bsdf_weight(
+        &self,
+        scatter_pdf: Option<f64>,
+        light: Option<&Arc<dyn Shape>>,
+        ray: &Ray,
+    ) -> f64 {
+        match (scatter_pdf, light) {
+            (Some(pdf), Some(light)) => self
+                .heuristic
+                .weight(pdf, light.pdf_value(ray.origin, ray.direction)),
+            _ => 1.0,
+        }
+    }
+
+    fn trace_path(
+        &self,
+        scene: &dyn Scene,
+        ray: Ray,
+        depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, PathStats) {
+        let light = scene.light();
+        let mut ray = ray;
+        let mut radiance = Color::zero();
+        let mut throughput = Color::one();
+        let mut stats = PathStats::new();
+        // 直前の散乱を材質の pdf でサンプリングした場合はその値 (カメラ・鏡面反射は None)
+        let mut scatter_pdf: Option<f64> = None;
+
+        for bounce in 0..=depth {
+            ray = seed_ray(ray, sampler);
+            let hit = match scene.world().hit(&ray, 0.001, f64::MAX) {
+                Some(hit) => hit,
+                None => {
+                    // 環境光は光源サンプリングでも数えているので重み付けする
+                    let weight = self.bsdf_weight(scatter_pdf, light, &ray);
+                    radiance += throughput * scene.background(ray.direction) * weight;
+                    stats.end(bounce, Termination::Escaped);
+                    break;
+                }
+            };
+
+            // 光源に直接当たった分は、光源サンプリングで数えた分と重み付けする
+            let emitted = hit.m.emitted(&ray, &hit);
+            if !emitted.near_zero() {
+                let weight = self.bsdf_weight(scatter_pdf, light, &ray);
+                radiance += throughput * emitted * weight;
+            }
+
+            if bounce == depth {
+                stats.end(bounce, Termination::MaxDepth);
+                break;
+            }
+            let lobes = hit.m.lobes(&hit);
+            if lobes.is_empty() {
+                stats.end(bounce, Termination::Absorbed);
+                break;
+            }
+            if !lobes.is_specular() {
+                radiance += throughput * self.sample_light(scene, &ray, &hit, sampler);
+            }
+
+            let frame = hit.frame();
+            let wo = frame.project(-ray.direction.normalize());
+            let sample = match hit.m.sample(&hit, wo, bsdf_random(sampler)) {
+                Some(sample) if !hit.leaks(frame.local(sample.wi)) => sample,
+                _ => {
+                    stats.end(bounce, Termination::Absorbed);
+                    break;
+                }
+            };
+            scatter_pdf = if sample.lobe.is_specular() {
+                None
+            } else {
+                Some(sample.pdf)
+            };
+            throughput = throughput * sample.weight;
+            ray = Ray::with_time(hit.p, frame.local(sample.wi), ray.time);
+
+            match self.roulette.survive(bounce, throughput, sampler) {
+                Some(weight) => throughput *= weight,
+                None => {
+                    stats.end(bounce, Termination::Roulette);
+                    break;
+                }
+            }
+        }
+        (radiance, stats)
+    }
+}
+
+impl Integrator for MisPathTracer {
+    fn trace(&self, scene: &dyn Scene, ray: Ray, depth: usize, sampler: &mut dyn Sampler) -> Color {
+        let path = self.trace_path(scene, ray, depth, sampler);
+        record(&self.statistics, path)
+    }
+
+    fn statistics(&self) -> Option<&PathStatistics> {
+        self.statistics.as_ref()
+    }
+}
+
+// アンビエントオクルージョン
+// 半球方向の光線が distance 以内で遮られなかった割合
+pub struct AmbientOcclusion {
+    distance: Option<f64>, // None ならシーンの大きさの 1/10
+}
+
+impl AmbientOcclusion {
+    const DEFAULT_RATIO: f64 = 0.1;
+
+    pub const fn new(distance: Option<f64>) -> Self {
+        Self { distance }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn trace(
+        &self,
+        scene: &dyn Scene,
+        ray: Ray,
+        _depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let world = scene.world();
+        let ray = seed_ray(ray, sampler);
+        if let Some(hit) = world.hit(&ray, 0.001, f64::MAX) {
+            let distance = self
+                .distance
+                .unwrap_or_else(|| world.bounding_box().extent().length() * Self::DEFAULT_RATIO);
+            // 裏面から見ている場合は法線を反転する
+            let n = if ray.direction.dot(hit.n) > 0.0 {
+                -hit.n
+            } else {
+                hit.n
+            };
+            let direction = ONB::new(n).local(Vec3::random_cosine_direction(sampler));
+            let occlusion_ray = seed_ray(Ray::with_time(hit.p, direction, ray.time), sampler);
+            if world.hit(&occlusion_ray, 0.001, distance).is_some() {
+                Color::zero()
+            } else {
+                Color::one()
+            }
+        } else {
+            Color::one()
+        }
+    }
+}
+
+// シェーディング法線を [0, 1] の色で表示する (デバッグ用)
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn trace(
+        &self,
+        scene: &dyn Scene,
+        ray: Ray,
+        _depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let ray = seed_ray(ray, sampler);
+        match scene.world().hit(&ray, 0.001, f64::MAX) {
+            Some(hit) => (hit.ns.normalize() + Vec3::one()) * 0.5,
+            None => Color::zero(),
+        }
+    }
+}
+
+// 最初に当たった材質の反射率 (光源は放射輝度) を表示する (デバッグ用)
+pub struct AlbedoIntegrator;
+
+impl Integrator for AlbedoIntegrator {
+    fn trace(
+        &self,
+        scene: &dyn Scene,
+        ray: Ray,
+        _depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let ray = seed_ray(ray, sampler);
+        match scene.world().hit(&ray, 0.001, f64::MAX) {
+            Some(hit) if hit.m.lobes(&hit).is_empty() => hit.m.emitted(&ray, &hit),
+            // 散乱の重みの期待値が、その向きから見た反射率になる
+            Some(hit) => {
+                let wo = hit.frame().project(-ray.direction.normalize());
+                hit.m
+                    .sample(&hit, wo, bsdf_random(sampler))
+                    .map_or(Color::zero(), |sample| sample.weight)
+            }
+            None => scene.background(ray.direction),
+        }
+    }
+}
+
+// 積分器の種類
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IntegratorKind {
+    #[default]
+    Path, // 光源サンプリングつきパストレーシング
+    BsdfPath,                      // 材質の pdf だけのパストレーシング
+    Mis(MisHeuristic),             // 次イベント推定 + MIS
+    AmbientOcclusion(Option<f64>), // 遮蔽を調べる距離
+    Normal,
+    Albedo,
+}
+
+impl IntegratorKind {
+    pub const NAMES: &'static [&'static str] = &[
+        "path",
+        "path-bsdf",
+        "mis[:balance|power]",
+        "ao[:DISTANCE]",
+        "normal",
+        "albedo",
+    ];
+
+    // statistics が true なら経路の統計を記録する (パストレーサーのみ)
+    pub fn create(&self, roulette: RussianRoulette, statistics: bool) -> Box<dyn Integrator> {
+        match *self {
+            IntegratorKind::Path => Box::new(LightPathTracer::new(roulette, statistics)),
+            IntegratorKind::BsdfPath => Box::new(PathTracer::new(roulette, statistics)),
+            IntegratorKind::Mis(heuristic) => {
+                Box::new(MisPathTracer::new(heuristic, roulette, statistics))
+            }
+            IntegratorKind::AmbientOcclusion(distance) => Box::new(AmbientOcclusion::new(distance)),
+            IntegratorKind::Normal => Box::new(NormalIntegrator),
+            IntegratorKind::Albedo => Box::new(AlbedoIntegrator),
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        match (name, param) {
+            ("path", None) => Ok(IntegratorKind::Path),
+            ("path-bsdf", None) => Ok(IntegratorKind::BsdfPath),
+            ("mis", None) | ("mis", Some("power")) => Ok(IntegratorKind::Mis(MisHeuristic::Power)),
+            ("mis", Some("balance")) => Ok(IntegratorKind::Mis(MisHeuristic::Balance)),
+            ("ao", None) => Ok(IntegratorKind::AmbientOcclusion(None)),
+            ("ao", Some(distance)) => match distance.parse::<f64>() {
+                Ok(distance) if distance > 0.0 => {
+                    Ok(IntegratorKind::AmbientOcclusion(Some(distance)))
+                }
+                _ => Err(format!("invalid occlusion distance {:?}", distance)),
+            },
+            ("normal", None) => Ok(IntegratorKind::Normal),
+            ("albedo", None) => Ok(IntegratorKind::Albedo),
+            _ => Err(format!(
+                "unknown integrator {:?} (expected one of: {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// シーンと積分器を組み合わせて描画できるようにする
+pub struct IntegratedScene<'a> {
+    scene: &'a dyn Scene,
+    integrator: Box<dyn Integrator>,
+}
+
+impl<'a> IntegratedScene<'a> {
+    pub fn new(scene: &'a dyn Scene, integrator: Box<dyn Integrator>) -> Self {
+        Self { scene, integrator }
+    }
+
+    pub fn statistics(&self) -> Option<&PathStatistics> {
+        self.integrator.statistics()
+    }
+}
+
+impl<'a> SceneWithDepth for IntegratedScene<'a> {
+    fn view(&self) -> View {
+        self.scene.view()
+    }
+
+    fn trace(&self, ray: Ray, depth: usize, sampler: &mut dyn Sampler) -> Color {
+        self.integrator.trace(self.scene, ray, depth, sampler)
+    }
+
+    fn width(&self) -> u32 {
+        self.scene.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.scene.height()
+    }
+
+    fn spp(&self) -> usize {
+        self.scene.spp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heuristic() {
+        assert_eq!(0.25, MisHeuristic::Balance.weight(1.0, 3.0));
+        assert_eq!(0.1, MisHeuristic::Power.weight(1.0, 3.0));
+        let w = MisHeuristic::Power.weight(2.0, 5.0) + MisHeuristic::Power.weight(5.0, 2.0);
+        assert!((w - 1.0).abs() < 1e-12);
+        assert_eq!(0.0, MisHeuristic::Balance.weight(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_roulette() {
+        let roulette = RussianRoulette::new(3);
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        // min_depth 回までは打ち切らず、重みも変えない
+        for bounce in 0..3 {
+            for _ in 0..100 {
+                let weight = roulette.survive(bounce, Color::full(0.01), sampler.as_mut());
+                assert_eq!(Some(1.0), weight);
+            }
+        }
+        // 生き残る確率 q と重み 1 / q の積の期待値は 1
+        let throughput = Color::new(0.3, 0.1, 0.2);
+        let n = 100_000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(weight) = roulette.survive(3, throughput, sampler.as_mut()) {
+                assert!((weight - 0.3_f64.recip()).abs() < 1e-12);
+                sum += weight;
+            }
+        }
+        assert!((sum / n as f64 - 1.0).abs() < 0.02);
+        // スループットが 1 以上なら必ず生き残る
+        assert_eq!(
+            Some(1.0),
+            roulette.survive(10, Color::full(2.0), sampler.as_mut())
+        );
+    }
+
+    // 再帰で書いた trace_path (乱数を同じ順に使う)
+    #[allow(clippy::too_many_arguments)]
+    fn trace_recursive(
+        scene: &dyn Scene,
+        light: Option<&Arc<dyn Shape>>,
+        roulette: RussianRoulette,
+        ray: &Ray,
+        throughput: Color,
+        bounce: usize,
+        depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let ray = &seed_ray(*ray, sampler);
+        let hit = match scene.world().hit(ray, 0.001, f64::MAX) {
+            Some(hit) => hit,
+            None => return scene.background(ray.direction),
+        };
+        let emitted = hit.m.emitted(ray, &hit);
+        let lobes = hit.m.lobes(&hit);
+        if bounce == depth || lobes.is_empty() {
+            return emitted;
+        }
+        let frame = hit.frame();
+        let wo = frame.project(-ray.direction.normalize());
+        let scattered = match light {
+            Some(light) if !lobes.is_specular() => {
+                let direction = if sampler.next_f64() < 0.5 {
+                    Some(light.random(hit.p, sampler))
+                } else {
+                    hit.m
+                        .sample(&hit, wo, bsdf_random(sampler))
+                        .map(|sample| frame.local(sample.wi))
+                };
+                direction.and_then(|direction| {
+                    let wi = frame.project(direction.normalize());
+                    let pdf =
+                        0.5 * light.pdf_value(hit.p, direction) + 0.5 * hit.m.pdf(&hit, wi, wo);
+                    (pdf > 0.0).then(|| (direction, hit.m.eval(&hit, wi, wo) / pdf))
+                })
+            }
+            _ => hit
+                .m
+                .sample(&hit, wo, bsdf_random(sampler))
+                .map(|sample| (frame.local(sample.wi), sample.weight)),
+        };
+        let (direction, weight) = match scattered {
+            Some((direction, weight)) if !hit.leaks(direction) => (direction, weight),
+            _ => return emitted,
+        };
+        let throughput = throughput * weight;
+        match roulette.survive(bounce, throughput, sampler) {
+            Some(rr) => {
+                let next = Ray::with_time(hit.p, direction, ray.time);
+                let incoming = trace_recursive(
+                    scene,
+                    light,
+                    roulette,
+                    &next,
+                    throughput * rr,
+                    bounce + 1,
+                    depth,
+                    sampler,
+                );
+                emitted + weight * rr * incoming
+            }
+            None => emitted,
+        }
+    }
+
+    #[test]
+    fn test_trace_path_matches_recursion() {
+        let scene = CornelBoxScene::new();
+        let roulette = RussianRoulette::new(2);
+        let view = scene.view();
+        let mut lit = 0;
+        for light in [None, scene.light()] {
+            for pixel in 0..64 {
+                // 奥の壁の 8x8 の点に向けて撃つ
+                let target = Vec3::new(
+                    40.0 + 60.0 * (pixel % 8) as f64,
+                    40.0 + 60.0 * (pixel / 8) as f64,
+                    555.0,
+                );
+                let ray = Ray::new(view.lookfrom, target - view.lookfrom);
+                for index in 0..4 {
+                    let mut sampler = SamplerKind::Independent.create(7, pixel, index, 4);
+                    let (expected, _) =
+                        trace_path(&scene, light, roulette, ray, 8, sampler.as_mut());
+                    let mut sampler = SamplerKind::Independent.create(7, pixel, index, 4);
+                    let actual = trace_recursive(
+                        &scene,
+                        light,
+                        roulette,
+                        &ray,
+                        Color::one(),
+                        0,
+                        8,
+                        sampler.as_mut(),
+                    );
+                    if !expected.near_zero() {
+                        lit += 1;
+                    }
+                    let diff = (actual - expected).length();
+                    assert!(
+                        diff <= 1e-9 * (1.0 + expected.length()),
+                        "{:?} {:?}",
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+        // 光が届いた経路もある
+        assert!(lit > 0);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Ok(IntegratorKind::Path), "path".parse());
+        assert_eq!(
+            Ok(IntegratorKind::AmbientOcclusion(Some(50.0))),
+            "ao:50".parse()
+        );
+        assert_eq!(
+            Ok(IntegratorKind::Mis(MisHeuristic::Balance)),
+            "mis:balance".parse()
+        );
+        assert!("ao:-1".parse::<IntegratorKind>().is_err());
+        assert!("mis:cutoff".parse::<IntegratorKind>().is_err());
+        assert!("whitted".parse::<IntegratorKind>().is_err());
+    }
+}
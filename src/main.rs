@@ -1,15 +1,35 @@
 #![allow(dead_code)]
 
+mod cli;
 mod consts;
+mod environment;
+mod integrator;
+mod medium;
+mod microfacet;
+mod obj;
+mod principled;
 mod rayt;
+mod scene_file;
 
+use cli::*;
 use consts::*;
+use environment::*;
+use integrator::*;
+use medium::*;
+use microfacet::*;
+use obj::*;
+use principled::*;
+use rayt::aabb::*;
 use rayt::camera::*;
 use rayt::float3::*;
 use rayt::onb::*;
+use rayt::perlin::*;
+use rayt::preview::*;
 use rayt::quat::*;
 use rayt::ray::*;
 use rayt::render::*;
+use rayt::sampler::*;
+use scene_file::*;
 use std::sync::Arc;
 
 trait Texture: Sync + Send {
@@ -19,15 +39,58 @@ trait Texture: Sync + Send {
 struct HitInfo {
     t: f64,               // 光線のパラメーター
     p: Point3,            // 衝突位置
-    n: Vec3,              // 衝突した位置の法線
+    n: Vec3,              // 衝突した位置の幾何法線 (面の表の向き)
+    ns: Vec3,             // シェーディング法線 (頂点法線の補間や法線マップで曲げたもの)
+    dpdu: Vec3,           // テキスチャ座標 u に沿った位置の変化 (接線)
+    dpdv: Vec3,           // テキスチャ座標 v に沿った位置の変化
     m: Arc<dyn Material>, // 材質
     u: f64,               // テキスチャ座標
     v: f64,               // テキスチャ座標
 }
 
 impl HitInfo {
-    const fn new(t: f64, p: Point3, n: Vec3, m: Arc<dyn Material>, u: f64, v: f64) -> Self {
-        Self { t, p, n, m, u, v }
+    fn new(t: f64, p: Point3, n: Vec3, m: Arc<dyn Material>, u: f64, v: f64) -> Self {
+        // 接線が分からない形状は 0 にしておき、必要になったら tangents で補う
+        Self {
+            t,
+            p,
+            n,
+            ns: n,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            m,
+            u,
+            v,
+        }
+    }
+
+    fn with_tangents(self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        Self { dpdu, dpdv, ..self }
+    }
+
+    fn with_shading_normal(self, ns: Vec3) -> Self {
+        Self { ns, ..self }
+    }
+
+    // 材質の局所座標 (シェーディング法線を z 軸、接線 dpdu の向きを x 軸にとる)
+    fn frame(&self) -> ONB {
+        ONB::with_tangent(self.ns, self.dpdu)
+    }
+
+    // (∂p/∂u, ∂p/∂v)。接線が分からなければ frame の法線に直交する軸で代用する
+    fn tangents(&self) -> (Vec3, Vec3) {
+        if self.dpdu.length_squared() > 0.0 {
+            (self.dpdu, self.dpdv)
+        } else {
+            let frame = self.frame();
+            (frame.u(), frame.v())
+        }
+    }
+
+    // w の向きが幾何法線とシェーディング法線で面の反対側になる
+    // (そのまま散乱させると面の裏へ光が漏れる)
+    fn leaks(&self, w: Vec3) -> bool {
+        w.dot(self.n) * w.dot(self.ns) < 0.0
     }
 }
 
@@ -44,7 +107,10 @@ impl Translate {
 
 impl Shape for Translate {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let moved_ray = Ray::new(ray.origin - self.offset, ray.direction);
+        let moved_ray = Ray {
+            origin: ray.origin - self.offset,
+            ..*ray
+        };
         if let Some(hit) = self.shape.hit(&moved_ray, t0, t1) {
             Some(HitInfo {
                 p: hit.p + self.offset,
@@ -54,7 +120,54 @@ impl Shape for Translate {
             None
         }
     }
+
+    fn bounding_box(&self) -> AABB {
+        let bbox = self.shape.bounding_box();
+        AABB::new(bbox.min + self.offset, bbox.max + self.offset)
+    }
 }
+
+// 時刻 0 から 1 の間に offset0 から offset1 まで動く平行移動
+struct MovingTranslate {
+    shape: Box<dyn Shape>,
+    offset0: Point3,
+    offset1: Point3,
+}
+
+impl MovingTranslate {
+    fn new(shape: Box<dyn Shape>, offset0: Point3, offset1: Point3) -> Self {
+        Self {
+            shape,
+            offset0,
+            offset1,
+        }
+    }
+
+    fn offset(&self, time: f64) -> Point3 {
+        self.offset0 + (self.offset1 - self.offset0) * time
+    }
+}
+
+impl Shape for MovingTranslate {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let offset = self.offset(ray.time);
+        let moved_ray = Ray {
+            origin: ray.origin - offset,
+            ..*ray
+        };
+        self.shape.hit(&moved_ray, t0, t1).map(|hit| HitInfo {
+            p: hit.p + offset,
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let bbox = self.shape.bounding_box();
+        AABB::new(bbox.min + self.offset0, bbox.max + self.offset0)
+            .surrounding(&AABB::new(bbox.min + self.offset1, bbox.max + self.offset1))
+    }
+}
+
 struct Rotate {
     shape: Box<dyn Shape>,
     quat: Quat,
@@ -72,17 +185,84 @@ impl Rotate {
 impl Shape for Rotate {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         let revq = self.quat.conj();
-        let rotated_ray = Ray::new(revq.rotate(ray.origin), revq.rotate(ray.direction));
+        let rotated_ray = Ray {
+            origin: revq.rotate(ray.origin),
+            direction: revq.rotate(ray.direction),
+            ..*ray
+        };
         if let Some(hit) = self.shape.hit(&rotated_ray, t0, t1) {
             Some(HitInfo {
                 p: self.quat.rotate(hit.p),
                 n: self.quat.rotate(hit.n),
+                ns: self.quat.rotate(hit.ns),
+                dpdu: self.quat.rotate(hit.dpdu),
+                dpdv: self.quat.rotate(hit.dpdv),
                 ..hit
             })
         } else {
             None
         }
     }
+
+    fn bounding_box(&self) -> AABB {
+        // 回転後の8頂点を囲む
+        self.shape
+            .bounding_box()
+            .corners()
+            .iter()
+            .fold(AABB::empty(), |acc, p| {
+                let p = self.quat.rotate(*p);
+                acc.surrounding(&AABB::new(p, p))
+            })
+    }
+}
+
+// 時刻 0 から 1 の間に quat0 から quat1 まで球面線形補間で回る回転
+struct MovingRotate {
+    shape: Box<dyn Shape>,
+    quat0: Quat,
+    quat1: Quat,
+}
+
+impl MovingRotate {
+    fn new(shape: Box<dyn Shape>, axis0: Vec3, angle0: f64, axis1: Vec3, angle1: f64) -> Self {
+        Self {
+            shape,
+            quat0: Quat::from_rot(axis0, angle0.to_radians()),
+            quat1: Quat::from_rot(axis1, angle1.to_radians()),
+        }
+    }
+}
+
+impl Shape for MovingRotate {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let quat = self.quat0.slerp(self.quat1, ray.time);
+        let revq = quat.conj();
+        let rotated_ray = Ray {
+            origin: revq.rotate(ray.origin),
+            direction: revq.rotate(ray.direction),
+            ..*ray
+        };
+        self.shape.hit(&rotated_ray, t0, t1).map(|hit| HitInfo {
+            p: quat.rotate(hit.p),
+            n: quat.rotate(hit.n),
+            ns: quat.rotate(hit.ns),
+            dpdu: quat.rotate(hit.dpdu),
+            dpdv: quat.rotate(hit.dpdv),
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        // 途中の向きでも収まるように、原点を中心に8頂点を含む球を囲む
+        let r = self
+            .shape
+            .bounding_box()
+            .corners()
+            .iter()
+            .fold(0.0_f64, |acc, p| acc.max(p.length()));
+        AABB::new(Point3::full(-r), Point3::full(r))
+    }
 }
 
 struct ColorTexture {
@@ -124,102 +304,343 @@ impl Texture for CheckerTexture {
     }
 }
 
-trait Material: Sync + Send {
-    // 散乱をシミュレート
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo>;
-    fn emitted(&self, _ray: &Ray, _hit: &HitInfo) -> Color {
-        Color::zero()
+// ノイズテクスチャで重ねるオクターブ数
+const NOISE_OCTAVES: usize = 7;
+
+// Perlin ノイズの値で2色を補間する
+struct NoiseTexture {
+    perlin: Perlin,
+    scale: f64,
+    color0: Color,
+    color1: Color,
+}
+
+impl NoiseTexture {
+    const fn new(perlin: Perlin, scale: f64, color0: Color, color1: Color) -> Self {
+        Self {
+            perlin,
+            scale,
+            color0,
+            color1,
+        }
     }
-    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitInfo) -> f64 {
-        0.0
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let t = 0.5 * (1.0 + self.perlin.noise(p * self.scale));
+        self.color0.lerp(self.color1, t)
     }
 }
 
-trait Pdf: Send + Sync {
-    fn value(&self, hit: &HitInfo, direction: Vec3) -> f64;
-    fn generate(&self, hit: &HitInfo) -> Vec3;
+// 大理石 (z 方向の縞を乱流で歪ませる)
+struct MarbleTexture {
+    perlin: Perlin,
+    scale: f64,
+    vein: Color,
+    base: Color,
 }
 
-struct CosinePdf {}
-impl CosinePdf {
-    const fn new() -> Self {
-        Self {}
+impl MarbleTexture {
+    const TURBULENCE: f64 = 10.0;
+
+    const fn new(perlin: Perlin, scale: f64, vein: Color, base: Color) -> Self {
+        Self {
+            perlin,
+            scale,
+            vein,
+            base,
+        }
     }
 }
 
-impl Pdf for CosinePdf {
-    fn value(&self, hit: &HitInfo, direction: Vec3) -> f64 {
-        let cosine = direction.normalize().dot(hit.n);
-        if cosine > 0.0 {
-            cosine * FRAC_1_PI
-        } else {
-            0.0
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let q = p * self.scale;
+        let turb = self.perlin.turbulence(q, NOISE_OCTAVES);
+        let t = 0.5 * (1.0 + (q.z() + Self::TURBULENCE * turb).sin());
+        self.vein.lerp(self.base, t)
+    }
+}
+
+// 木目 (y 軸まわりの年輪をノイズで揺らす)
+struct WoodTexture {
+    perlin: Perlin,
+    scale: f64,
+    light: Color,
+    dark: Color,
+}
+
+impl WoodTexture {
+    const GRAIN: f64 = 0.6;
+
+    const fn new(perlin: Perlin, scale: f64, light: Color, dark: Color) -> Self {
+        Self {
+            perlin,
+            scale,
+            light,
+            dark,
         }
     }
+}
 
-    fn generate(&self, hit: &HitInfo) -> Vec3 {
-        ONB::new(hit.n).local(Vec3::random_cosine_direction())
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let q = p * self.scale;
+        let r = (q.x() * q.x() + q.z() * q.z()).sqrt();
+        let ring = (r + Self::GRAIN * self.perlin.fbm(q, 3)).fract();
+        // 年輪の境目をはっきりさせる
+        self.light.lerp(self.dark, ring * ring)
     }
 }
 
-struct ShapePdf {
-    shape: Arc<dyn Shape>,
-    origin: Point3,
+// 雲 (fBm をそのまま濃さにする)
+struct CloudyTexture {
+    perlin: Perlin,
+    scale: f64,
+    sky: Color,
+    cloud: Color,
 }
-impl ShapePdf {
-    fn new(shape: Arc<dyn Shape>, origin: Point3) -> Self {
-        Self { shape, origin }
+
+impl CloudyTexture {
+    const fn new(perlin: Perlin, scale: f64, sky: Color, cloud: Color) -> Self {
+        Self {
+            perlin,
+            scale,
+            sky,
+            cloud,
+        }
     }
 }
-impl Pdf for ShapePdf {
-    fn value(&self, _hit: &HitInfo, direction: Vec3) -> f64 {
-        self.shape.pdf_value(self.origin, direction)
-    }
 
-    fn generate(&self, _hit: &HitInfo) -> Vec3 {
-        self.shape.random(self.origin)
+impl Texture for CloudyTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let density = self.perlin.fbm(p * self.scale, NOISE_OCTAVES);
+        let t = (0.5 + 1.5 * density).clamp(0.0, 1.0);
+        self.sky.lerp(self.cloud, t)
     }
 }
 
-struct MixturePdf {
-    pdfs: [Arc<dyn Pdf>; 2],
+// 画像テクスチャの補間方法
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextureFilter {
+    Nearest,
+    Bilinear,
 }
 
-impl MixturePdf {
-    fn new(pdf0: Arc<dyn Pdf>, pdf1: Arc<dyn Pdf>) -> Self {
-        Self { pdfs: [pdf0, pdf1] }
+// 範囲外のテクスチャ座標の扱い
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    // 画素のインデックスを [0, n) に収める
+    fn apply(&self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+
+    // 整数に直す前に、周期 (ミラーは往復の 2n) か範囲の1画素外までに縮めて桁あふれを防ぐ
+    fn reduce(&self, x: f64, n: usize) -> f64 {
+        let n = n as f64;
+        match self {
+            WrapMode::Repeat | WrapMode::Mirror => x.rem_euclid(2.0 * n),
+            WrapMode::Clamp => x.clamp(-1.0, n + 1.0),
+        }
     }
 }
 
-impl Pdf for MixturePdf {
-    fn value(&self, hit: &HitInfo, direction: Vec3) -> f64 {
-        let pdf0 = self.pdfs[0].value(&hit, direction);
-        let pdf1 = self.pdfs[1].value(&hit, direction);
+// 画像ファイルのテクスチャ
+struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>, // リニア空間の色 (open_linear なら画素値そのまま)
+    filter: TextureFilter,
+    wrap: WrapMode,
+}
 
-        0.5 * pdf0 + 0.5 * pdf1
+impl ImageTexture {
+    fn open(path: impl AsRef<std::path::Path>) -> image::ImageResult<Self> {
+        Self::load(path, true)
     }
 
-    fn generate(&self, hit: &HitInfo) -> Vec3 {
-        if Vec3::random_full().x() < 0.5 {
-            self.pdfs[0].generate(hit)
-        } else {
-            self.pdfs[1].generate(hit)
+    // 色ではないデータ (法線マップなど) の画像はガンマを外さずに読む
+    fn open_linear(path: impl AsRef<std::path::Path>) -> image::ImageResult<Self> {
+        Self::load(path, false)
+    }
+
+    fn load(path: impl AsRef<std::path::Path>, degamma: bool) -> image::ImageResult<Self> {
+        let img = image::open(path)?.to_rgb8();
+        let (width, height) = img.dimensions();
+        let pixels = img
+            .pixels()
+            .map(|p| {
+                let c = Color::from_rgb(p[0], p[1], p[2]);
+                if degamma {
+                    c.degamma(GAMMA_FACTOR)
+                } else {
+                    c
+                }
+            })
+            .collect();
+        Ok(Self {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+            filter: TextureFilter::Bilinear,
+            wrap: WrapMode::Repeat,
+        })
+    }
+
+    fn filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    fn wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = self.wrap.apply(x, self.width);
+        let y = self.wrap.apply(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        if !u.is_finite() || !v.is_finite() {
+            return Color::zero();
+        }
+        // 画像の1行目が v = 1
+        let x = self.wrap.reduce(u * self.width as f64, self.width);
+        let y = self
+            .wrap
+            .reduce((1.0 - v) * self.height as f64, self.height);
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            TextureFilter::Bilinear => {
+                // 画素の中心を基準に周囲4画素を補間する
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = self.texel(x0, y0).lerp(self.texel(x0 + 1, y0), tx);
+                let bottom = self.texel(x0, y0 + 1).lerp(self.texel(x0 + 1, y0 + 1), tx);
+                top.lerp(bottom, ty)
+            }
         }
     }
 }
 
-struct ScatterInfo {
-    ray: Ray,      // 散乱後の光の向き
-    albedo: Color, // 反射率(アルベド)
-    pdf: Option<Arc<dyn Pdf>>,
+// 散乱の成分の種類 (ビットの組み合わせ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lobe(u8);
+
+impl Lobe {
+    const NONE: Lobe = Lobe(0);
+    const REFLECTION: Lobe = Lobe(1);
+    const TRANSMISSION: Lobe = Lobe(1 << 1);
+    const DIFFUSE: Lobe = Lobe(1 << 2);
+    const GLOSSY: Lobe = Lobe(1 << 3);
+    const SPECULAR: Lobe = Lobe(1 << 4); // 向きが1つに決まる (eval・pdf では評価できない)
+
+    const fn contains(self, other: Lobe) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    const fn is_specular(self) -> bool {
+        self.contains(Lobe::SPECULAR)
+    }
 }
 
-impl ScatterInfo {
-    fn new(ray: Ray, albedo: Color, pdf: Option<Arc<dyn Pdf>>) -> Self {
-        Self { ray, albedo, pdf }
+impl std::ops::BitOr for Lobe {
+    type Output = Lobe;
+
+    fn bitor(self, rhs: Lobe) -> Lobe {
+        Lobe(self.0 | rhs.0)
     }
 }
 
+// 材質をサンプリングした結果
+struct BsdfSample {
+    wi: Vec3,      // 散乱後の向き (局所座標)
+    weight: Color, // BSDF × |cosθ| / pdf (スループットにかける)
+    pdf: f64,      // 立体角あたりの確率密度 (鏡面では 0)
+    lobe: Lobe,
+}
+
+impl BsdfSample {
+    const fn new(wi: Vec3, weight: Color, pdf: f64, lobe: Lobe) -> Self {
+        Self {
+            wi,
+            weight,
+            pdf,
+            lobe,
+        }
+    }
+
+    // 向きが1つに決まる散乱 (weight は反射率)
+    const fn specular(wi: Vec3, weight: Color, lobe: Lobe) -> Self {
+        Self::new(wi, weight, 0.0, Lobe(lobe.0 | Lobe::SPECULAR.0))
+    }
+}
+
+// 向きは衝突位置の局所座標 (HitInfo::frame、法線が z 軸) で表す。
+// wo は光線の逆向き、wi は散乱後の向きで、どちらも面から離れる向き (裏から当たると wo.z < 0)。
+trait Material: Sync + Send {
+    // 持っている散乱の成分 (散乱しない材質は NONE、SPECULAR を含む材質は鏡面の成分しか持たない)
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::NONE
+    }
+    // wo から wi に散乱する強さ (BSDF × |cosθi|)。鏡面の成分は含まない
+    fn eval(&self, _hit: &HitInfo, _wi: Vec3, _wo: Vec3) -> Color {
+        Color::zero()
+    }
+    // u は [0, 1) の乱数 (u[0] は成分の選択、u[1], u[2] は向き)。散乱しなければ None
+    fn sample(&self, _hit: &HitInfo, _wo: Vec3, _u: [f64; 3]) -> Option<BsdfSample> {
+        None
+    }
+    // sample で wi が選ばれる確率密度 (鏡面の成分は含まない)
+    fn pdf(&self, _hit: &HitInfo, _wi: Vec3, _wo: Vec3) -> f64 {
+        0.0
+    }
+    fn emitted(&self, _ray: &Ray, _hit: &HitInfo) -> Color {
+        Color::zero()
+    }
+}
+
+// 面の同じ側にある向きか
+fn same_hemisphere(w0: Vec3, w1: Vec3) -> bool {
+    w0.z() * w1.z() > 0.0
+}
+
+// 局所座標での鏡面反射
+fn reflect_local(wo: Vec3) -> Vec3 {
+    Vec3::new(-wo.x(), -wo.y(), wo.z())
+}
+
 struct DiffuseLight {
     emit: Box<dyn Texture>,
 }
@@ -231,10 +652,6 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _ray: &Ray, _hit: &HitInfo) -> Option<ScatterInfo> {
-        None
-    }
-
     fn emitted(&self, ray: &Ray, hit: &HitInfo) -> Color {
         if ray.direction.dot(hit.n) < 0.0 {
             self.emit.value(hit.u, hit.v, hit.p)
@@ -247,25 +664,49 @@ impl Material for DiffuseLight {
 // 拡散反射するような材質
 struct Lambertian {
     albedo: Box<dyn Texture>,
-    pdf: Arc<dyn Pdf>,
 }
 
 impl Lambertian {
     fn new(albedo: Box<dyn Texture>) -> Self {
-        Self {
-            albedo,
-            pdf: Arc::new(CosinePdf::new()),
-        }
+        Self { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::DIFFUSE | Lobe::REFLECTION
+    }
+
+    fn eval(&self, hit: &HitInfo, wi: Vec3, wo: Vec3) -> Color {
+        if !same_hemisphere(wi, wo) {
+            return Color::zero();
+        }
+        self.albedo.value(hit.u, hit.v, hit.p) * (wi.z().abs() * FRAC_1_PI)
+    }
+
+    fn sample(&self, hit: &HitInfo, wo: Vec3, [_, u1, u2]: [f64; 3]) -> Option<BsdfSample> {
+        let wi = Vec3::cosine_direction([u1, u2]);
+        // wo と同じ側に散乱する
+        let wi = if wo.z() < 0.0 { -wi } else { wi };
+        let pdf = self.pdf(hit, wi, wo);
+        if pdf <= 0.0 {
+            return None;
+        }
         let albedo = self.albedo.value(hit.u, hit.v, hit.p);
-        Some(ScatterInfo::new(*ray, albedo, Some(Arc::clone(&self.pdf))))
+        Some(BsdfSample::new(
+            wi,
+            albedo,
+            pdf,
+            Lobe::DIFFUSE | Lobe::REFLECTION,
+        ))
     }
-    fn scattering_pdf(&self, ray: &Ray, hit: &HitInfo) -> f64 {
-        ray.direction.normalize().dot(hit.n).max(0.0) * FRAC_1_PI
+
+    fn pdf(&self, _hit: &HitInfo, wi: Vec3, wo: Vec3) -> f64 {
+        if same_hemisphere(wi, wo) {
+            wi.z().abs() * FRAC_1_PI
+        } else {
+            0.0
+        }
     }
 }
 
@@ -280,16 +721,23 @@ impl Metal {
     }
 }
 
+// fuzz でずらした向きの分布は評価できないので、鏡面として扱う
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let mut reflected = ray.direction.normalize().reflect(hit.n);
-        reflected = reflected + self.fuzz * Vec3::random_in_unit_sphere();
-        if reflected.dot(hit.n) > 0.0 {
-            let albedo = self.albedo.value(hit.u, hit.v, hit.p);
-            Some(ScatterInfo::new(Ray::new(hit.p, reflected), albedo, None))
-        } else {
-            None
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::SPECULAR | Lobe::REFLECTION
+    }
+
+    fn sample(&self, hit: &HitInfo, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let reflected = reflect_local(wo).normalize() + self.fuzz * Vec3::in_unit_sphere(u);
+        if !same_hemisphere(reflected, wo) {
+            return None;
         }
+        let albedo = self.albedo.value(hit.u, hit.v, hit.p);
+        Some(BsdfSample::specular(
+            reflected.normalize(),
+            albedo,
+            Lobe::REFLECTION,
+        ))
     }
 }
 
@@ -309,30 +757,32 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let reflected = ray.direction.reflect(hit.n);
-        let (outward_normal, ni_over_nt, cosine) = {
-            let dot = ray.direction.dot(hit.n);
-            if dot > 0.0 {
-                (-hit.n, self.ri, self.ri * dot / ray.direction.length())
-            } else {
-                (hit.n, self.ri.recip(), -dot / ray.direction.length())
-            }
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::SPECULAR | Lobe::REFLECTION | Lobe::TRANSMISSION
+    }
+
+    fn sample(&self, _hit: &HitInfo, wo: Vec3, [u0, _, _]: [f64; 3]) -> Option<BsdfSample> {
+        let wo = wo.normalize();
+        let (outward_normal, ni_over_nt, cosine) = if wo.z() < 0.0 {
+            (-Vec3::zaxis(), self.ri, -self.ri * wo.z())
+        } else {
+            (Vec3::zaxis(), self.ri.recip(), wo.z())
         };
 
-        if let Some(refracted) = (-ray.direction).refract(outward_normal, ni_over_nt) {
-            if Vec3::random_full().x() > Self::schlick(cosine, self.ri) {
-                return Some(ScatterInfo::new(
-                    Ray::new(hit.p, refracted),
-                    Color::one(),
-                    None,
+        if let Some(refracted) = wo.refract(outward_normal, ni_over_nt) {
+            if u0 > Self::schlick(cosine, self.ri) {
+                // 屈折で放射輝度は (屈折率の比)² 倍になる (RoughDielectric と同じ)
+                return Some(BsdfSample::specular(
+                    refracted.normalize(),
+                    Color::full(ni_over_nt.powi(2)),
+                    Lobe::TRANSMISSION,
                 ));
             }
         }
-        Some(ScatterInfo::new(
-            Ray::new(hit.p, reflected),
+        Some(BsdfSample::specular(
+            reflect_local(wo),
             Color::one(),
-            None,
+            Lobe::REFLECTION,
         ))
     }
 }
@@ -345,11 +795,14 @@ trait Shape: Send + Sync {
         t1: f64,
     ) -> Option<HitInfo>;
 
+    // 形状を囲む軸平行境界ボックス
+    fn bounding_box(&self) -> AABB;
+
     fn pdf_value(&self, _o: Vec3, _v: Vec3) -> f64 {
         0.0
     }
 
-    fn random(&self, _o: Vec3) -> Vec3 {
+    fn random(&self, _o: Vec3, _sampler: &mut dyn Sampler) -> Vec3 {
         Vec3::xaxis()
     }
 }
@@ -374,11 +827,26 @@ impl Sphere {
         let theta = p.y().asin();
         (1.0 - (phi + PI) / PI2, (theta + PI / 2.0) * FRAC_1_PI)
     }
-}
 
-impl Shape for Sphere {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let oc = ray.origin - self.center;
+    // uv の微分 (∂p/∂u, ∂p/∂v)
+    fn tangents(&self, n: Vec3) -> (Vec3, Vec3) {
+        let phi = n.z().atan2(n.x());
+        let (sin_theta, cos_theta) = (n.y(), (1.0 - n.y() * n.y()).max(0.0).sqrt());
+        let dpdu = Vec3::new(phi.sin(), 0.0, -phi.cos()) * (PI2 * self.radius * cos_theta);
+        let dpdv = Vec3::new(-sin_theta * phi.cos(), cos_theta, -sin_theta * phi.sin())
+            * (PI * self.radius);
+        (dpdu, dpdv)
+    }
+
+    fn hit_info(&self, t: f64, p: Point3, n: Vec3) -> HitInfo {
+        let (u, v) = Self::uv(n);
+        let (dpdu, dpdv) = self.tangents(n);
+        HitInfo::new(t, p, n, Arc::clone(&self.material), u, v).with_tangents(dpdu, dpdv)
+    }
+
+    // 中心を指定して交差判定する (動く球と共用)
+    fn hit_at(&self, center: Point3, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let oc = ray.origin - center;
         let a = ray.direction.dot(ray.direction);
         let b = 2.0 * ray.direction.dot(oc);
         let c = oc.dot(oc) - self.radius.powi(2);
@@ -388,20 +856,24 @@ impl Shape for Sphere {
             let temp = (-b - root) / (2.0 * a);
             if t0 < temp && temp < t1 {
                 let p = ray.at(temp);
-                let n = (p - self.center) / self.radius;
-                let (u, v) = Self::uv(n);
-                return Some(HitInfo::new(temp, p, n, Arc::clone(&self.material), u, v));
+                let n = (p - center) / self.radius;
+                return Some(self.hit_info(temp, p, n));
             }
             let temp = (-b + root) / (2.0 * a);
             if t0 < temp && temp < t1 {
                 let p = ray.at(temp);
-                let n = (p - self.center) / self.radius;
-                let (u, v) = Self::uv(n);
-                return Some(HitInfo::new(temp, p, n, Arc::clone(&self.material), u, v));
+                let n = (p - center) / self.radius;
+                return Some(self.hit_info(temp, p, n));
             }
         }
         None
     }
+}
+
+impl Shape for Sphere {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.hit_at(self.center, ray, t0, t1)
+    }
 
     fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
         if let Some(_) = self.hit(&Ray::new(o, v), 0.001, f64::MAX) {
@@ -415,10 +887,65 @@ impl Shape for Sphere {
         }
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
         let direction = self.center - o;
         let distance_squared = direction.length_squared();
-        ONB::new(direction).local(Vec3::random_to_sphere(self.radius, distance_squared))
+        ONB::new(direction).local(Vec3::random_to_sphere(
+            self.radius,
+            distance_squared,
+            sampler,
+        ))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let r = Vec3::full(self.radius);
+        AABB::new(self.center - r, self.center + r)
+    }
+}
+
+// 時刻 0 から 1 の間に center0 から center1 まで動く球
+struct MovingSphere {
+    sphere: Sphere, // 時刻 0 の球
+    center1: Point3,
+}
+
+impl MovingSphere {
+    const fn new(
+        center0: Point3,
+        center1: Point3,
+        radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            sphere: Sphere::new(center0, radius, material),
+            center1,
+        }
+    }
+
+    fn center(&self, time: f64) -> Point3 {
+        self.sphere.center + (self.center1 - self.sphere.center) * time
+    }
+}
+
+impl Shape for MovingSphere {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.sphere.hit_at(self.center(ray.time), ray, t0, t1)
+    }
+
+    // 光源としてのサンプリングには時刻がないので、時刻 0 の位置で行う
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.sphere.pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.sphere.random(o, sampler)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let r = Vec3::full(self.sphere.radius);
+        self.sphere
+            .bounding_box()
+            .surrounding(&AABB::new(self.center1 - r, self.center1 + r))
     }
 }
 
@@ -464,18 +991,19 @@ impl Shape for Rect {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         let mut origin = ray.origin;
         let mut direction = ray.direction;
-        let mut axis = Vec3::zaxis();
+        // 面内の2軸と法線
+        let mut axis = [Vec3::xaxis(), Vec3::yaxis(), Vec3::zaxis()];
         match self.axis {
             RectAxisType::XY => {}
             RectAxisType::XZ => {
                 origin = Point3::new(origin.x(), origin.z(), origin.y());
                 direction = Vec3::new(direction.x(), direction.z(), direction.y());
-                axis = Vec3::yaxis();
+                axis = [Vec3::xaxis(), Vec3::zaxis(), Vec3::yaxis()];
             }
             RectAxisType::YZ => {
                 origin = Point3::new(origin.y(), origin.z(), origin.x());
                 direction = Vec3::new(direction.y(), direction.z(), direction.x());
-                axis = Vec3::xaxis();
+                axis = [Vec3::yaxis(), Vec3::zaxis(), Vec3::xaxis()];
             }
         }
 
@@ -490,14 +1018,17 @@ impl Shape for Rect {
             return None;
         }
 
-        Some(HitInfo::new(
-            t,
-            ray.at(t),
-            axis,
-            Arc::clone(&self.material),
-            (x - self.x0) / (self.x1 - self.x0),
-            (y - self.y0) / (self.y1 - self.y0),
-        ))
+        Some(
+            HitInfo::new(
+                t,
+                ray.at(t),
+                axis[2],
+                Arc::clone(&self.material),
+                (x - self.x0) / (self.x1 - self.x0),
+                (y - self.y0) / (self.y1 - self.y0),
+            )
+            .with_tangents(axis[0] * (self.x1 - self.x0), axis[1] * (self.y1 - self.y0)),
+        )
     }
 
     fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
@@ -511,15 +1042,317 @@ impl Shape for Rect {
         }
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
-        let [rx, ry, _] = Vec3::random().to_array();
-        let x = self.x0 + rx * (self.x1 - self.x0);
-        let y = self.y0 + ry * (self.y1 - self.y0);
-        match self.axis {
-            RectAxisType::XY => Point3::new(x, y, self.k) - o,
-            RectAxisType::XZ => Point3::new(x, self.k, y) - o,
-            RectAxisType::YZ => Point3::new(self.k, x, y) - o,
-        }
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let [rx, ry] = sampler.next_2d();
+        let x = self.x0 + rx * (self.x1 - self.x0);
+        let y = self.y0 + ry * (self.y1 - self.y0);
+        match self.axis {
+            RectAxisType::XY => Point3::new(x, y, self.k) - o,
+            RectAxisType::XZ => Point3::new(x, self.k, y) - o,
+            RectAxisType::YZ => Point3::new(self.k, x, y) - o,
+        }
+    }
+
+    fn bounding_box(&self) -> AABB {
+        // 厚みが0だと交差判定できないので少しだけ膨らませる
+        let (k0, k1) = (self.k - 0.0001, self.k + 0.0001);
+        match self.axis {
+            RectAxisType::XY => AABB::new(
+                Point3::new(self.x0, self.y0, k0),
+                Point3::new(self.x1, self.y1, k1),
+            ),
+            RectAxisType::XZ => AABB::new(
+                Point3::new(self.x0, k0, self.y0),
+                Point3::new(self.x1, k1, self.y1),
+            ),
+            RectAxisType::YZ => AABB::new(
+                Point3::new(k0, self.x0, self.y0),
+                Point3::new(k1, self.x1, self.y1),
+            ),
+        }
+    }
+}
+
+// 三角形メッシュの頂点バッファ (三角形間で共有する)
+struct MeshData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+}
+
+impl MeshData {
+    fn new(positions: Vec<Point3>, normals: Vec<Vec3>, uvs: Vec<[f64; 2]>) -> Self {
+        Self {
+            positions,
+            normals,
+            uvs,
+        }
+    }
+}
+
+// 三角形の頂点インデックス (法線・テクスチャ座標は省略できる)
+#[derive(Debug, Clone, Copy)]
+struct Face {
+    v: [usize; 3],
+    vn: Option<[usize; 3]>,
+    vt: Option<[usize; 3]>,
+}
+
+impl Face {
+    const fn new(v: [usize; 3]) -> Self {
+        Self {
+            v,
+            vn: None,
+            vt: None,
+        }
+    }
+
+    fn vertices(&self, mesh: &MeshData) -> [Point3; 3] {
+        let [i0, i1, i2] = self.v;
+        let p = &mesh.positions;
+        [p[i0], p[i1], p[i2]]
+    }
+
+    // 頂点法線によらない面の向き (長さは面積の2倍)
+    fn geometric_normal(&self, mesh: &MeshData) -> Vec3 {
+        let [p0, p1, p2] = self.vertices(mesh);
+        (p1 - p0).cross(p2 - p0)
+    }
+
+    fn area(&self, mesh: &MeshData) -> f64 {
+        self.geometric_normal(mesh).length() * 0.5
+    }
+
+    // 三角形上の一様な点
+    fn random_point(&self, mesh: &MeshData, sampler: &mut dyn Sampler) -> Point3 {
+        let [p0, p1, p2] = self.vertices(mesh);
+        let [r1, r2] = sampler.next_2d();
+        let s = r1.sqrt();
+        p0 * (1.0 - s) + p1 * (s * (1.0 - r2)) + p2 * (s * r2)
+    }
+}
+
+#[derive(Clone)]
+struct Triangle {
+    mesh: Arc<MeshData>,
+    face: Face,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    fn new(p0: Point3, p1: Point3, p2: Point3, material: Arc<dyn Material>) -> Self {
+        let mesh = MeshData::new(vec![p0, p1, p2], Vec::new(), Vec::new());
+        Self::from_mesh(Arc::new(mesh), Face::new([0, 1, 2]), material)
+    }
+
+    fn with_normals(
+        [p0, p1, p2]: [Point3; 3],
+        [n0, n1, n2]: [Vec3; 3],
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let mesh = MeshData::new(vec![p0, p1, p2], vec![n0, n1, n2], Vec::new());
+        let face = Face {
+            vn: Some([0, 1, 2]),
+            ..Face::new([0, 1, 2])
+        };
+        Self::from_mesh(Arc::new(mesh), face, material)
+    }
+
+    fn from_mesh(mesh: Arc<MeshData>, face: Face, material: Arc<dyn Material>) -> Self {
+        Self {
+            mesh,
+            face,
+            material,
+        }
+    }
+
+    fn vertices(&self) -> [Point3; 3] {
+        self.face.vertices(&self.mesh)
+    }
+
+    fn area(&self) -> f64 {
+        self.face.area(&self.mesh)
+    }
+
+    // 辺 e1, e2 と頂点のテクスチャ座標から ∂p/∂u, ∂p/∂v を求める
+    // (テクスチャ座標が潰れていれば None)
+    fn tangents(e1: Vec3, e2: Vec3, [uv0, uv1, uv2]: [[f64; 2]; 3]) -> Option<(Vec3, Vec3)> {
+        let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+        let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+        let det = du1 * dv2 - dv1 * du2;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = det.recip();
+        Some((
+            (e1 * dv2 - e2 * dv1) * inv_det,
+            (e2 * du1 - e1 * du2) * inv_det,
+        ))
+    }
+}
+
+impl Shape for Triangle {
+    // Möller–Trumbore 法
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let [p0, p1, p2] = self.vertices();
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = ray.direction.cross(e2);
+        let det = e1.dot(pvec);
+        if det.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let inv_det = det.recip();
+        let tvec = ray.origin - p0;
+        let u = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let v = ray.direction.dot(qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t < t0 || t > t1 {
+            return None;
+        }
+
+        // 重心座標で頂点の属性を補間する
+        // 頂点法線があればシェーディング法線にして、幾何法線はその側に向ける
+        let w = 1.0 - u - v;
+        let mut n = e1.cross(e2).normalize();
+        let ns = if let Some([i0, i1, i2]) = self.face.vn {
+            let ns = &self.mesh.normals;
+            let ns = (ns[i0] * w + ns[i1] * u + ns[i2] * v).normalize();
+            if n.dot(ns) < 0.0 {
+                n = -n;
+            }
+            ns
+        } else {
+            n
+        };
+        let (tu, tv, dpdu, dpdv) = if let Some([i0, i1, i2]) = self.face.vt {
+            let uv = &self.mesh.uvs;
+            let (dpdu, dpdv) = Self::tangents(e1, e2, [uv[i0], uv[i1], uv[i2]]).unwrap_or((e1, e2));
+            (
+                uv[i0][0] * w + uv[i1][0] * u + uv[i2][0] * v,
+                uv[i0][1] * w + uv[i1][1] * u + uv[i2][1] * v,
+                dpdu,
+                dpdv,
+            )
+        } else {
+            (u, v, e1, e2)
+        };
+
+        Some(
+            HitInfo::new(t, ray.at(t), n, Arc::clone(&self.material), tu, tv)
+                .with_tangents(dpdu, dpdv)
+                .with_shading_normal(ns),
+        )
+    }
+
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        if let Some(hit) = self.hit(&Ray::new(o, v), 0.001, f64::MAX) {
+            let n = self.face.geometric_normal(&self.mesh).normalize();
+            let distance_squared = hit.t.powi(2) * v.length_squared();
+            let cosine = v.dot(n).abs() / v.length();
+            distance_squared / (cosine * self.area())
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.face.random_point(&self.mesh, sampler) - o
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let [p0, p1, p2] = self.vertices();
+        let bbox = AABB::from_points(p0, p1).surrounding(&AABB::new(p2, p2));
+        // 軸に平行な三角形でも厚みを持たせる
+        let pad = Vec3::full(0.0001);
+        AABB::new(bbox.min - pad, bbox.max + pad)
+    }
+}
+
+// 頂点バッファを共有する三角形の集まり
+struct TriangleMesh {
+    triangles: BvhNode,
+    mesh: Arc<MeshData>,
+    faces: Vec<Face>, // 光源として使うときのサンプリング用 (cdf と同じ順)
+    cdf: Vec<f64>,    // 面積の累積分布
+    area: f64,
+}
+
+impl TriangleMesh {
+    fn new(mesh: Arc<MeshData>, faces: Vec<Face>, material: Arc<dyn Material>) -> Self {
+        let triangles = BvhNode::new(
+            faces
+                .iter()
+                .map(|face| {
+                    Box::new(Triangle::from_mesh(
+                        Arc::clone(&mesh),
+                        *face,
+                        Arc::clone(&material),
+                    )) as Box<dyn Shape>
+                })
+                .collect(),
+        );
+
+        let mut area = 0.0;
+        let cdf = faces
+            .iter()
+            .map(|face| {
+                area += face.area(&mesh);
+                area
+            })
+            .collect();
+
+        Self {
+            triangles,
+            mesh,
+            faces,
+            cdf,
+            area,
+        }
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.triangles.hit(ray, t0, t1)
+    }
+
+    // 面積に比例して点を選ぶので、光線上の全ての交差点の寄与を足し合わせる
+    // 面積から立体角への変換には幾何法線を使う (補間した頂点法線では面の向きと合わない)
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        let ray = Ray::new(o, v);
+        let mut pdf = 0.0;
+        let mut t0 = 0.001;
+        while let Some(hit) = self.hit(&ray, t0, f64::MAX) {
+            let distance_squared = hit.t.powi(2) * v.length_squared();
+            let cosine = v.dot(hit.n).abs() / v.length();
+            if cosine > 0.0 {
+                pdf += distance_squared / (cosine * self.area);
+            }
+            t0 = hit.t + 0.001;
+        }
+        pdf
+    }
+
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let r = sampler.next_f64() * self.area;
+        let index = self
+            .cdf
+            .partition_point(|&x| x < r)
+            .min(self.faces.len() - 1);
+        self.faces[index].random_point(&self.mesh, sampler) - o
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.triangles.bounding_box()
     }
 }
 
@@ -536,17 +1369,116 @@ impl FlipFace {
 impl Shape for FlipFace {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         if let Some(hit) = self.shape.hit(ray, t0, t1) {
-            Some(HitInfo { n: -hit.n, ..hit })
+            Some(HitInfo {
+                n: -hit.n,
+                ns: -hit.ns,
+                ..hit
+            })
         } else {
             None
         }
     }
+
+    fn bounding_box(&self) -> AABB {
+        self.shape.bounding_box()
+    }
+}
+
+// 接空間の法線マップ
+// テクスチャの RGB を [-1, 1] に戻し、接線 (u 方向)・従法線 (v 方向)・法線の成分として読む
+struct NormalMap {
+    shape: Box<dyn Shape>,
+    map: Box<dyn Texture>,
+}
+
+impl NormalMap {
+    fn new(shape: Box<dyn Shape>, map: Box<dyn Texture>) -> Self {
+        Self { shape, map }
+    }
+}
+
+impl Shape for NormalMap {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.shape.hit(ray, t0, t1).map(|hit| {
+            let ns = hit.ns;
+            let tangent = hit.frame().u();
+            // 従法線は v の増える側に向ける
+            let bitangent = ns.cross(tangent);
+            let (_, dpdv) = hit.tangents();
+            let bitangent = if bitangent.dot(dpdv) < 0.0 {
+                -bitangent
+            } else {
+                bitangent
+            };
+            let c = self.map.value(hit.u, hit.v, hit.p) * 2.0 - Vec3::one();
+            let n = tangent * c.x() + bitangent * c.y() + ns * c.z();
+            if n.length_squared() > 0.0 {
+                hit.with_shading_normal(n.normalize())
+            } else {
+                hit
+            }
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.shape.bounding_box()
+    }
+}
+
+// 高さテクスチャ (R の値 × scale) の分だけ法線方向に面をずらしたとみなすバンプマップ
+struct BumpMap {
+    shape: Box<dyn Shape>,
+    height: Box<dyn Texture>,
+    scale: f64,
+}
+
+impl BumpMap {
+    // 高さの差分をとるテクスチャ座標の幅
+    const DELTA: f64 = 0.0005;
+
+    fn new(shape: Box<dyn Shape>, height: Box<dyn Texture>, scale: f64) -> Self {
+        Self {
+            shape,
+            height,
+            scale,
+        }
+    }
+
+    fn height(&self, hit: &HitInfo, du: f64, dv: f64) -> f64 {
+        let (dpdu, dpdv) = hit.tangents();
+        let p = hit.p + dpdu * du + dpdv * dv;
+        self.height.value(hit.u + du, hit.v + dv, p).x() * self.scale
+    }
+}
+
+impl Shape for BumpMap {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.shape.hit(ray, t0, t1).map(|hit| {
+            let h = self.height(&hit, 0.0, 0.0);
+            let dhdu = (self.height(&hit, Self::DELTA, 0.0) - h) / Self::DELTA;
+            let dhdv = (self.height(&hit, 0.0, Self::DELTA) - h) / Self::DELTA;
+            // (∂p/∂u + ∂h/∂u ns) × (∂p/∂v + ∂h/∂v ns) の、ns に沿った成分を元の法線に置き換えたもの
+            let ns = hit.ns;
+            let (dpdu, dpdv) = hit.tangents();
+            let n = ns * dpdu.cross(dpdv).dot(ns) + ns.cross(dpdv) * dhdu + dpdu.cross(ns) * dhdv;
+            let n = if n.dot(ns) < 0.0 { -n } else { n };
+            if n.length_squared() > 0.0 {
+                hit.with_shading_normal(n.normalize())
+            } else {
+                hit
+            }
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.shape.bounding_box()
+    }
 }
 
 struct Box3D {
     p0: Point3,
     p1: Point3,
-    shapes: ShapeList,
+    shapes: BvhNode,
 }
 
 impl Box3D {
@@ -593,7 +1525,11 @@ impl Box3D {
                 .build(),
         );
 
-        Self { p0, p1, shapes }
+        Self {
+            p0,
+            p1,
+            shapes: BvhNode::from_list(shapes),
+        }
     }
 }
 
@@ -601,6 +1537,10 @@ impl Shape for Box3D {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         self.shapes.hit(ray, t0, t1)
     }
+
+    fn bounding_box(&self) -> AABB {
+        AABB::from_points(self.p0, self.p1)
+    }
 }
 
 struct ShapeList {
@@ -644,13 +1584,174 @@ impl Shape for ShapeList {
             .fold(0.0, |acc, s| acc + weight * s.pdf_value(o, v))
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
         if self.objects.is_empty() {
             panic!();
         }
 
-        let index = (Vec3::random_full().x() * self.objects.len() as f64).floor() as usize;
-        self.objects[index].random(o)
+        let index = (sampler.next_f64() * self.objects.len() as f64).floor() as usize;
+        self.objects[index].random(o, sampler)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.objects
+            .iter()
+            .fold(AABB::empty(), |acc, s| acc.surrounding(&s.bounding_box()))
+    }
+}
+
+// 境界ボリューム階層
+// ShapeList と同じく子の形状を1つの形状として扱えるが、交差判定は O(log n) で済む
+struct BvhNode {
+    left: Box<dyn Shape>,
+    right: Option<Box<dyn Shape>>,
+    left_count: usize, // 左右に含まれる葉の数 (pdf_value/random の重みに使う)
+    right_count: usize,
+    bbox: AABB,
+}
+
+impl BvhNode {
+    // SAH の分割候補を評価する最大の要素数 (これより多い場合は中央値で分割)
+    const SAH_MAX_OBJECTS: usize = 4096;
+
+    fn new(objects: Vec<Box<dyn Shape>>) -> Self {
+        if objects.is_empty() {
+            panic!("BvhNode: no objects");
+        }
+
+        let objects = objects
+            .into_iter()
+            .map(|s| (s.bounding_box(), s))
+            .collect::<Vec<_>>();
+        Self::build(objects)
+    }
+
+    fn from_list(list: ShapeList) -> Self {
+        Self::new(list.objects)
+    }
+
+    fn build(mut objects: Vec<(AABB, Box<dyn Shape>)>) -> Self {
+        let bbox = objects
+            .iter()
+            .fold(AABB::empty(), |acc, (b, _)| acc.surrounding(b));
+
+        match objects.len() {
+            1 => {
+                let (_, left) = objects.pop().unwrap();
+                Self {
+                    left,
+                    right: None,
+                    left_count: 1,
+                    right_count: 0,
+                    bbox,
+                }
+            }
+            2 => {
+                let (_, right) = objects.pop().unwrap();
+                let (_, left) = objects.pop().unwrap();
+                Self {
+                    left,
+                    right: Some(right),
+                    left_count: 1,
+                    right_count: 1,
+                    bbox,
+                }
+            }
+            n => {
+                // 重心の広がりが最も大きい軸で並べ替える
+                let centroid_bounds = objects.iter().fold(AABB::empty(), |acc, (b, _)| {
+                    let c = b.centroid();
+                    acc.surrounding(&AABB::new(c, c))
+                });
+                let axis = centroid_bounds.longest_axis();
+                objects.sort_by(|(a, _), (b, _)| {
+                    let a = a.centroid().to_array()[axis];
+                    let b = b.centroid().to_array()[axis];
+                    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                });
+
+                let mid = if n <= Self::SAH_MAX_OBJECTS {
+                    Self::sah_split(&objects)
+                } else {
+                    n / 2
+                };
+                let right_objects = objects.split_off(mid);
+                let left = Self::build(objects);
+                let right = Self::build(right_objects);
+                Self {
+                    left_count: left.left_count + left.right_count,
+                    right_count: right.left_count + right.right_count,
+                    left: Box::new(left),
+                    right: Some(Box::new(right)),
+                    bbox,
+                }
+            }
+        }
+    }
+
+    // 表面積ヒューリスティックで最もコストの低い分割位置を求める
+    fn sah_split(objects: &[(AABB, Box<dyn Shape>)]) -> usize {
+        let n = objects.len();
+        // right_areas[i] は objects[i..] を囲む箱の表面積
+        let mut right_areas = vec![0.0; n];
+        let mut acc = AABB::empty();
+        for i in (1..n).rev() {
+            acc = acc.surrounding(&objects[i].0);
+            right_areas[i] = acc.surface_area();
+        }
+
+        let mut best = (f64::MAX, n / 2);
+        let mut acc = AABB::empty();
+        for i in 1..n {
+            acc = acc.surrounding(&objects[i - 1].0);
+            let cost = i as f64 * acc.surface_area() + (n - i) as f64 * right_areas[i];
+            if cost < best.0 {
+                best = (cost, i);
+            }
+        }
+        best.1
+    }
+}
+
+impl Shape for BvhNode {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        if !self.bbox.hit(ray, t0, t1) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(ray, t0, t1);
+        let closest_so_far = hit_left.as_ref().map_or(t1, |hit| hit.t);
+        let hit_right = self
+            .right
+            .as_ref()
+            .and_then(|right| right.hit(ray, t0, closest_so_far));
+
+        hit_right.or(hit_left)
+    }
+
+    // ShapeList と同様に全ての葉を等しい重みで扱う
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        let total = (self.left_count + self.right_count) as f64;
+        let left = self.left_count as f64 * self.left.pdf_value(o, v);
+        let right = self
+            .right
+            .as_ref()
+            .map_or(0.0, |right| self.right_count as f64 * right.pdf_value(o, v));
+        (left + right) / total
+    }
+
+    fn random(&self, o: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let total = (self.left_count + self.right_count) as f64;
+        match &self.right {
+            Some(right) if sampler.next_f64() * total >= self.left_count as f64 => {
+                right.random(o, sampler)
+            }
+            _ => self.left.random(o, sampler),
+        }
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
     }
 }
 
@@ -674,6 +1775,18 @@ impl ShapeBuilder {
         self
     }
 
+    // 画像を読み込めなければエラーを返す
+    fn image_texture(
+        mut self,
+        path: impl AsRef<std::path::Path>,
+        filter: TextureFilter,
+        wrap: WrapMode,
+    ) -> image::ImageResult<Self> {
+        let texture = ImageTexture::open(path)?.filter(filter).wrap(wrap);
+        self.texture = Some(Box::new(texture));
+        Ok(self)
+    }
+
     fn checker_texture(mut self, odd_color: Color, even_color: Color, freq: f64) -> Self {
         self.texture = Some(Box::new(CheckerTexture::new(
             Box::new(ColorTexture::new(odd_color)),
@@ -683,6 +1796,30 @@ impl ShapeBuilder {
         self
     }
 
+    fn noise_texture(mut self, seed: u64, scale: f64, color0: Color, color1: Color) -> Self {
+        let perlin = Perlin::new(seed);
+        self.texture = Some(Box::new(NoiseTexture::new(perlin, scale, color0, color1)));
+        self
+    }
+
+    fn marble_texture(mut self, seed: u64, scale: f64, vein: Color, base: Color) -> Self {
+        let perlin = Perlin::new(seed);
+        self.texture = Some(Box::new(MarbleTexture::new(perlin, scale, vein, base)));
+        self
+    }
+
+    fn wood_texture(mut self, seed: u64, scale: f64, light: Color, dark: Color) -> Self {
+        let perlin = Perlin::new(seed);
+        self.texture = Some(Box::new(WoodTexture::new(perlin, scale, light, dark)));
+        self
+    }
+
+    fn cloudy_texture(mut self, seed: u64, scale: f64, sky: Color, cloud: Color) -> Self {
+        let perlin = Perlin::new(seed);
+        self.texture = Some(Box::new(CloudyTexture::new(perlin, scale, sky, cloud)));
+        self
+    }
+
     // material
 
     fn material(mut self, material: Arc<dyn Material>) -> Self {
@@ -708,6 +1845,43 @@ impl ShapeBuilder {
         self
     }
 
+    // 粗い金属 (roughness が 0 なら鏡面)
+    fn conductor(mut self, preset: ConductorPreset, roughness: f64) -> Self {
+        self.material = Some(Arc::new(Conductor::preset(preset, roughness)));
+        self
+    }
+
+    // 粗いガラス (roughness が 0 なら滑らかなガラス)
+    fn rough_dielectric(mut self, ri: f64, roughness: f64) -> Self {
+        self.material = Some(Arc::new(RoughDielectric::new(ri, roughness)));
+        self
+    }
+
+    // テクスチャを base_color にした principled BSDF (他のパラメーターは material で指定する)
+    fn principled(mut self, metallic: f64, roughness: f64) -> Self {
+        let constant = |x: f64| -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::full(x))) };
+        self.material = Some(Arc::new(
+            Principled::new(self.texture.unwrap())
+                .metallic(constant(metallic))
+                .roughness(constant(roughness)),
+        ));
+        self.texture = None;
+        self
+    }
+
+    // 媒質の位相関数 (constant_medium と組み合わせる)
+    fn isotropic(mut self) -> Self {
+        self.material = Some(Arc::new(Isotropic::new(self.texture.unwrap())));
+        self.texture = None;
+        self
+    }
+
+    fn henyey_greenstein(mut self, g: f64) -> Self {
+        self.material = Some(Arc::new(HenyeyGreenstein::new(self.texture.unwrap(), g)));
+        self.texture = None;
+        self
+    }
+
     fn diffuse_light(mut self) -> Self {
         self.material = Some(Arc::new(DiffuseLight::new(self.texture.unwrap())));
         self.texture = None;
@@ -725,6 +1899,17 @@ impl ShapeBuilder {
         self
     }
 
+    fn moving_sphere(mut self, center0: Point3, center1: Point3, radius: f64) -> Self {
+        self.shape = Some(Box::new(MovingSphere::new(
+            center0,
+            center1,
+            radius,
+            self.material.unwrap(),
+        )));
+        self.material = None;
+        self
+    }
+
     fn rect_xy(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
         self.shape = Some(Box::new(Rect::new(
             x0,
@@ -767,17 +1952,75 @@ impl ShapeBuilder {
         self
     }
 
+    fn triangle(mut self, p0: Point3, p1: Point3, p2: Point3) -> Self {
+        self.shape = Some(Box::new(Triangle::new(p0, p1, p2, self.material.unwrap())));
+        self.material = None;
+        self
+    }
+
+    fn triangle_mesh(mut self, mesh: Arc<MeshData>, faces: Vec<Face>) -> Self {
+        self.shape = Some(Box::new(TriangleMesh::new(
+            mesh,
+            faces,
+            self.material.unwrap(),
+        )));
+        self.material = None;
+        self
+    }
+
+    // OBJ ファイルを読み込む (材質を指定した場合は MTL より優先する)
+    fn obj(mut self, path: impl AsRef<std::path::Path>) -> Result<Self, ObjError> {
+        let model = ObjModel::load(path)?;
+        self.shape = Some(model.into_shape(self.material.take()));
+        Ok(self)
+    }
+
     fn box3d(mut self, p0: Point3, p1: Point3) -> Self {
         self.shape = Some(Box::new(Box3D::new(p0, p1, self.material.unwrap())));
         self.material = None;
         self
     }
 
+    fn shape(mut self, shape: Box<dyn Shape>) -> Self {
+        self.shape = Some(shape);
+        self
+    }
+
     fn flip_face(mut self) -> Self {
         self.shape = Some(Box::new(FlipFace::new(self.shape.unwrap())));
         self
     }
 
+    // 物体 (bbox) と視点を囲む球の中を一様な霧で満たす (材質は位相関数)
+    fn fog(mut self, bbox: AABB, lookfrom: Point3, density: f64) -> Self {
+        let phase = self.material.take().unwrap();
+        self.shape = Some(Box::new(ConstantMedium::fog(
+            bbox, lookfrom, density, phase,
+        )));
+        self
+    }
+
+    // 形状の内側を密度 density の媒質にする (phase は isotropic などの位相関数)
+    fn constant_medium(mut self, density: f64, phase: Arc<dyn Material>) -> Self {
+        self.shape = Some(Box::new(ConstantMedium::new(
+            self.shape.unwrap(),
+            density,
+            phase,
+        )));
+        self
+    }
+
+    // 接空間の法線マップ (画像は open_linear で読み込む)
+    fn normal_map(mut self, map: Box<dyn Texture>) -> Self {
+        self.shape = Some(Box::new(NormalMap::new(self.shape.unwrap(), map)));
+        self
+    }
+
+    fn bump_map(mut self, height: Box<dyn Texture>, scale: f64) -> Self {
+        self.shape = Some(Box::new(BumpMap::new(self.shape.unwrap(), height, scale)));
+        self
+    }
+
     fn translate(mut self, offset: Point3) -> Self {
         self.shape = Some(Box::new(Translate::new(self.shape.unwrap(), offset)));
         self
@@ -788,18 +2031,45 @@ impl ShapeBuilder {
         self
     }
 
+    fn moving_translate(mut self, offset0: Point3, offset1: Point3) -> Self {
+        self.shape = Some(Box::new(MovingTranslate::new(
+            self.shape.unwrap(),
+            offset0,
+            offset1,
+        )));
+        self
+    }
+
+    fn moving_rotate(mut self, axis0: Vec3, angle0: f64, axis1: Vec3, angle1: f64) -> Self {
+        self.shape = Some(Box::new(MovingRotate::new(
+            self.shape.unwrap(),
+            axis0,
+            angle0,
+            axis1,
+            angle1,
+        )));
+        self
+    }
+
     fn build(self) -> Box<dyn Shape> {
         self.shape.unwrap()
     }
 }
 
 struct CornelBoxScene {
-    world: ShapeList,
+    world: BvhNode,
     light: Arc<dyn Shape>,
 }
 
 impl CornelBoxScene {
+    const LOOKFROM: Point3 = Point3::new(278.0, 278.0, -800.0);
+
     fn new() -> Self {
+        Self::with_fog(None)
+    }
+
+    // fog に密度を指定すると、箱とカメラを一様な霧で満たす
+    fn with_fog(fog: Option<f64>) -> Self {
         let mut world = ShapeList::new();
 
         let red = Color::new(0.64, 0.05, 0.05);
@@ -888,62 +2158,44 @@ impl CornelBoxScene {
                 .build(),
         );
 
+        if let Some(density) = fog {
+            let bbox = world.bounding_box();
+            world.push(
+                ShapeBuilder::new()
+                    .color_texture(Color::one())
+                    .isotropic()
+                    .fog(bbox, Self::LOOKFROM, density)
+                    .build(),
+            );
+        }
+
         Self {
-            world,
+            world: BvhNode::from_list(world),
             light: Arc::new(light),
         }
     }
-
-    fn background(&self, _d: Vec3) -> Color {
-        Color::full(0.0)
-    }
 }
 
-impl SceneWithDepth for CornelBoxScene {
-    fn camera(&self) -> Camera {
-        Camera::from_lookat(
-            Vec3::new(278.0, 278.0, -800.0),
+impl Scene for CornelBoxScene {
+    fn view(&self) -> View {
+        View::new(
+            Self::LOOKFROM,
             Vec3::new(278.0, 278.0, 0.0),
             Vec3::yaxis(),
             40.0,
-            self.aspect(),
         )
     }
 
-    fn trace(&self, ray: Ray, depth: usize) -> Color {
-        let hit_info = self.world.hit(&ray, 0.001, f64::MAX);
+    fn world(&self) -> &dyn Shape {
+        &self.world
+    }
 
-        if let Some(hit) = hit_info {
-            let emitted = hit.m.emitted(&ray, &hit);
-            let scatter_info = if depth > 0 {
-                hit.m.scatter(&ray, &hit)
-            } else {
-                None
-            };
-            if let Some(scatter) = scatter_info {
-                if let Some(pdf) = scatter.pdf {
-                    let shape_pdf = Arc::new(ShapePdf::new(Arc::clone(&self.light), hit.p));
-                    let pdf = MixturePdf::new(shape_pdf, Arc::clone(&pdf));
-
-                    let new_ray = Ray::new(hit.p, pdf.generate(&hit));
-
-                    let spdf_value = pdf.value(&hit, new_ray.direction);
-                    if spdf_value > 0.0 {
-                        let pdf_value = hit.m.scattering_pdf(&new_ray, &hit);
-                        let albedo = scatter.albedo * pdf_value;
-                        emitted + albedo * self.trace(new_ray, depth - 1) / spdf_value
-                    } else {
-                        emitted
-                    }
-                } else {
-                    emitted + scatter.albedo * self.trace(scatter.ray, depth - 1)
-                }
-            } else {
-                emitted
-            }
-        } else {
-            self.background(ray.direction)
-        }
+    fn light(&self) -> Option<&Arc<dyn Shape>> {
+        Some(&self.light)
+    }
+
+    fn background(&self, _d: Vec3) -> Color {
+        Color::full(0.0)
     }
 
     // fn spp(&self) -> usize {
@@ -951,6 +2203,473 @@ impl SceneWithDepth for CornelBoxScene {
     // }
 }
 
+// 組み込みシーン (名前, 説明, 生成関数)
+type SceneFactory = fn() -> Box<dyn Scene>;
+const BUILTIN_SCENES: &[(&str, &str, SceneFactory)] = &[
+    (
+        "cornell_box",
+        "Cornell box with a glass sphere and a rotated box",
+        || Box::new(CornelBoxScene::new()),
+    ),
+    ("cornell_fog", "Cornell box filled with thin fog", || {
+        Box::new(CornelBoxScene::with_fog(Some(0.001)))
+    }),
+];
+
 fn main() {
-    render_aa_with_depth(CornelBoxScene::new());
+    let scene_list = BUILTIN_SCENES
+        .iter()
+        .map(|(name, about, _)| (*name, *about))
+        .collect::<Vec<_>>();
+    let args = Args::parse(&scene_list, BUILTIN_SCENES[0].0);
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+
+    let scene = if let Some((_, _, factory)) = BUILTIN_SCENES
+        .iter()
+        .find(|(name, _, _)| *name == args.scene)
+    {
+        factory()
+    } else {
+        match FileScene::load(&args.scene) {
+            Ok(scene) => Box::new(scene),
+            Err(e) => {
+                eprintln!("{}: {}", args.scene, e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let scene = IntegratedScene::new(
+        scene.as_ref(),
+        args.integrator
+            .create(RussianRoulette::new(args.roulette_depth), args.stats),
+    );
+    if args.preview {
+        preview_with_config(&scene, &args.config);
+    } else if let Err(e) = render_with_config(&scene, &args.config) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(statistics) = scene.statistics() {
+        println!("{}", statistics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lambertian(color: Color) -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Box::new(ColorTexture::new(color))))
+    }
+
+    #[test]
+    fn test_wrap_mode() {
+        let cases = [
+            (
+                WrapMode::Repeat,
+                [(-5, 3), (-1, 3), (0, 0), (3, 3), (4, 0), (9, 1)],
+            ),
+            (
+                WrapMode::Clamp,
+                [(-5, 0), (-1, 0), (0, 0), (3, 3), (4, 3), (9, 3)],
+            ),
+            (
+                WrapMode::Mirror,
+                [(-5, 3), (-1, 0), (0, 0), (3, 3), (4, 3), (9, 1)],
+            ),
+        ];
+        for (wrap, expects) in cases.iter() {
+            for &(i, expect) in expects.iter() {
+                assert_eq!(expect, wrap.apply(i, 4), "{:?} {}", wrap, i);
+            }
+        }
+    }
+
+    // sample の pdf と重みが pdf・eval と一致し、光を増やさない
+    #[test]
+    fn test_bsdf() {
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let texture = |x: f64| -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::full(x))) };
+        let hit = HitInfo::new(
+            1.0,
+            Point3::zero(),
+            Vec3::zaxis(),
+            lambertian(Color::full(0.8)),
+            0.0,
+            0.0,
+        );
+        let (outside, inside) = (Vec3::new(0.6, 0.0, 0.8), Vec3::new(0.0, 0.6, -0.8));
+        // (材質, wo, eval の積分の期待値)
+        let cases: [(Box<dyn Material>, Vec3, f64); 6] = [
+            (Box::new(Lambertian::new(texture(0.8))), outside, 0.8),
+            (Box::new(Lambertian::new(texture(0.8))), inside, 0.8),
+            (Box::new(Metal::new(texture(0.9), 0.0)), outside, 0.0),
+            (Box::new(Metal::new(texture(0.9), 0.3)), outside, 0.0),
+            (Box::new(Dielectric::new(1.5)), outside, 0.0),
+            (Box::new(Dielectric::new(1.5)), inside, 0.0),
+        ];
+        for (material, wo, expected) in cases.iter() {
+            let n = 4000;
+            let mut albedo = 0.0;
+            for _ in 0..n {
+                let u = [sampler.next_f64(), sampler.next_f64(), sampler.next_f64()];
+                let sample = match material.sample(&hit, *wo, u) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let pdf = material.pdf(&hit, sample.wi, *wo);
+                assert!((sample.pdf - pdf).abs() <= 1e-9 * pdf);
+                let eval = material.eval(&hit, sample.wi, *wo);
+                if sample.lobe.is_specular() {
+                    assert_eq!(0.0, sample.pdf);
+                    assert!(eval.near_zero());
+                } else {
+                    assert!((sample.weight - eval / sample.pdf).near_zero());
+                }
+                // 屈折では放射輝度が (屈折率の比)² 倍になるので、その分を戻して比べる
+                // (ガラスの屈折率はどれも 1.5)
+                let weight = if sample.lobe.contains(Lobe::TRANSMISSION) {
+                    let eta = if wo.z() > 0.0 { 1.5 } else { 1.5f64.recip() };
+                    sample.weight * (eta * eta)
+                } else {
+                    sample.weight
+                };
+                assert!(weight.iter().all(|x| (0.0..=1.0 + 1e-9).contains(x)));
+                albedo += weight.x();
+            }
+            assert!(albedo / n as f64 <= 1.0);
+
+            // 球面を θ, φ の格子に分けて積分する
+            let (nt, np) = (200, 400);
+            let (dt, dp) = (PI / nt as f64, PI2 / np as f64);
+            let mut integral = 0.0;
+            for i in 0..nt {
+                let (sin_t, cos_t) = ((i as f64 + 0.5) * dt).sin_cos();
+                for j in 0..np {
+                    let (sin_p, cos_p) = ((j as f64 + 0.5) * dp).sin_cos();
+                    let wi = Vec3::new(sin_t * cos_p, sin_t * sin_p, cos_t);
+                    integral += material.eval(&hit, wi, *wo).x() * sin_t * dt * dp;
+                }
+            }
+            assert!(integral <= 1.0);
+            assert!((integral - expected).abs() < 0.01, "{}", integral);
+        }
+
+        // 2種類の滑らかなガラスは、屈折したときの重みが同じ
+        let rough = RoughDielectric::new(1.5, 0.0);
+        for wo in [outside, inside, Vec3::zaxis(), -Vec3::zaxis()] {
+            let a = Dielectric::new(1.5)
+                .sample(&hit, wo, [0.99, 0.5, 0.5])
+                .unwrap();
+            let b = rough.sample(&hit, wo, [0.99, 0.5, 0.5]).unwrap();
+            assert!(a.lobe.contains(Lobe::TRANSMISSION) && b.lobe.contains(Lobe::TRANSMISSION));
+            assert!(
+                (a.weight - b.weight).near_zero(),
+                "{:?} {:?}",
+                a.weight,
+                b.weight
+            );
+            assert!((a.wi - b.wi).near_zero());
+        }
+    }
+
+    #[test]
+    fn test_image_texture() {
+        let (a, b, c, d) = (
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        );
+        let texture = |filter, wrap| ImageTexture {
+            width: 2,
+            height: 2,
+            pixels: vec![a, b, c, d],
+            filter,
+            wrap,
+        };
+        // 4画素の中心の間は平均、画素の中心はその画素の色
+        let bilinear = texture(TextureFilter::Bilinear, WrapMode::Clamp);
+        let mid = bilinear.value(0.5, 0.5, Point3::zero());
+        assert!((mid - (a + b + c + d) / 4.0).near_zero());
+        assert!((bilinear.value(0.25, 0.75, Point3::zero()) - a).near_zero());
+        assert!((bilinear.value(0.75, 0.25, Point3::zero()) - d).near_zero());
+
+        let nearest = texture(TextureFilter::Nearest, WrapMode::Repeat);
+        assert_eq!(b, nearest.value(0.75, 0.75, Point3::zero()));
+        assert_eq!(b, nearest.value(-0.25, 1.75, Point3::zero()));
+
+        // 範囲外の値でもあふれない
+        for &wrap in &[WrapMode::Repeat, WrapMode::Clamp, WrapMode::Mirror] {
+            let texture = texture(TextureFilter::Bilinear, wrap);
+            for &u in &[f64::NAN, f64::INFINITY, 1e300, -1e300] {
+                texture.value(u, 0.5, Point3::zero());
+                texture.value(0.5, u, Point3::zero());
+            }
+        }
+
+        // 読み込めない画像はエラーになる
+        let missing = ShapeBuilder::new().image_texture(
+            "no_such_texture.png",
+            TextureFilter::Bilinear,
+            WrapMode::Repeat,
+        );
+        assert!(missing.is_err());
+    }
+
+    // 頂点法線を傾けても、光源としての pdf は面の向きで決まる
+    #[test]
+    fn test_mesh_pdf_value() {
+        let positions = vec![
+            Point3::new(-1.0, 0.0, -1.0),
+            Point3::new(1.0, 0.0, -1.0),
+            Point3::new(1.0, 0.0, 1.0),
+            Point3::new(-1.0, 0.0, 1.0),
+        ];
+        let tilted = vec![Vec3::new(1.0, 1.0, 0.0).normalize(); 4];
+        let faces = vec![Face::new([0, 1, 2]), Face::new([0, 2, 3])];
+        let smooth_faces = faces
+            .iter()
+            .map(|face| Face {
+                vn: Some(face.v),
+                ..*face
+            })
+            .collect();
+        let light = lambertian(Color::one());
+        let flat = TriangleMesh::new(
+            Arc::new(MeshData::new(positions.clone(), Vec::new(), Vec::new())),
+            faces,
+            Arc::clone(&light),
+        );
+        let smooth = TriangleMesh::new(
+            Arc::new(MeshData::new(positions, tilted, Vec::new())),
+            smooth_faces,
+            light,
+        );
+
+        // 真上から距離 2 で面積 4 の面を見る
+        let o = Point3::new(0.3, 2.0, 0.2);
+        let v = -Vec3::yaxis();
+        assert!((flat.pdf_value(o, v) - 1.0).abs() < 1e-9);
+        assert!((smooth.pdf_value(o, v) - 1.0).abs() < 1e-9);
+        let v = Vec3::new(0.5, -2.0, 0.1);
+        assert!((smooth.pdf_value(o, v) - flat.pdf_value(o, v)).abs() < 1e-9);
+    }
+
+    // BvhNode は置き換える前の ShapeList と同じ交差を返す
+    #[test]
+    fn test_bvh_closest_hit() {
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let mut point = |scale: f64| {
+            Point3::new(
+                sampler.next_f64() - 0.5,
+                sampler.next_f64() - 0.5,
+                sampler.next_f64() - 0.5,
+            ) * scale
+        };
+        let mut list = ShapeList::new();
+        let mut shapes: Vec<Box<dyn Shape>> = Vec::new();
+        for i in 0..64 {
+            let (p0, p1, p2) = (point(10.0), point(2.0), point(2.0));
+            let material = lambertian(Color::full(i as f64 / 64.0));
+            if i % 2 == 0 {
+                list.push(Box::new(Sphere::new(p0, 0.5, Arc::clone(&material))));
+                shapes.push(Box::new(Sphere::new(p0, 0.5, material)));
+            } else {
+                list.push(Box::new(Triangle::new(
+                    p0,
+                    p0 + p1,
+                    p0 + p2,
+                    Arc::clone(&material),
+                )));
+                shapes.push(Box::new(Triangle::new(p0, p0 + p1, p0 + p2, material)));
+            }
+        }
+        let bvh = BvhNode::new(shapes);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // 物体の集まりを向いたレイ
+            let origin = point(40.0);
+            let ray = Ray::new(origin, point(10.0) - origin);
+            let expect = list.hit(&ray, 0.001, f64::MAX);
+            let actual = bvh.hit(&ray, 0.001, f64::MAX);
+            match (expect, actual) {
+                (Some(expect), Some(actual)) => {
+                    assert_eq!(expect.t, actual.t);
+                    assert_eq!(expect.p, actual.p);
+                    assert_eq!(expect.n, actual.n);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("BvhNode and ShapeList disagree"),
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    // bbox が点 p を中心とする半径 r の球を囲んでいるか
+    fn surrounds(bbox: &AABB, p: Point3, r: f64) -> bool {
+        let lower = p - Vec3::full(r) - bbox.min;
+        let upper = bbox.max - p - Vec3::full(r);
+        lower.iter().chain(upper.iter()).all(|x| *x > -1e-9)
+    }
+
+    #[test]
+    fn test_moving_sphere() {
+        let (center0, center1) = (Point3::zero(), Point3::new(10.0, 0.0, 0.0));
+        let sphere = MovingSphere::new(center0, center1, 1.0, lambertian(Color::full(0.5)));
+        let ray = |x, time| Ray::with_time(Point3::new(x, 0.0, -5.0), Vec3::zaxis(), time);
+        // 時刻に応じた位置で当たる
+        for &(time, x) in &[(0.0, 0.0), (0.5, 5.0), (1.0, 10.0)] {
+            let hit = sphere.hit(&ray(x, time), 0.001, f64::MAX).unwrap();
+            assert!((hit.p - Point3::new(x, 0.0, -1.0)).near_zero());
+            assert!((hit.t - 4.0).abs() < 1e-9);
+        }
+        assert!(sphere.hit(&ray(10.0, 0.0), 0.001, f64::MAX).is_none());
+        assert!(sphere.hit(&ray(0.0, 1.0), 0.001, f64::MAX).is_none());
+
+        let bbox = sphere.bounding_box();
+        assert!(surrounds(&bbox, center0, 1.0));
+        assert!(surrounds(&bbox, center1, 1.0));
+
+        // 光源としては時刻 0 の位置でサンプリングする
+        let o = Point3::new(0.0, 0.0, -5.0);
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        for _ in 0..100 {
+            let direction = sphere.random(o, sampler.as_mut());
+            assert!(sphere.pdf_value(o, direction) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_moving_translate() {
+        let (offset0, offset1) = (Point3::zero(), Point3::new(0.0, 4.0, 0.0));
+        let sphere = Sphere::new(Point3::zero(), 1.0, lambertian(Color::full(0.5)));
+        let shape = MovingTranslate::new(Box::new(sphere), offset0, offset1);
+        // 当たった位置は時刻に応じた offset だけずれる
+        for &time in &[0.0, 0.25, 1.0] {
+            let offset = Point3::new(0.0, 4.0 * time, 0.0);
+            let ray = Ray::with_time(offset + Point3::new(0.0, 0.0, -5.0), Vec3::zaxis(), time);
+            let hit = shape.hit(&ray, 0.001, f64::MAX).unwrap();
+            assert!((hit.p - (offset + Point3::new(0.0, 0.0, -1.0))).near_zero());
+            assert!((hit.n - Vec3::new(0.0, 0.0, -1.0)).near_zero());
+        }
+        let bbox = shape.bounding_box();
+        assert!(surrounds(&bbox, offset0, 1.0));
+        assert!(surrounds(&bbox, offset1, 1.0));
+    }
+
+    // u に比例した高さ
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _p: Point3) -> Color {
+            Color::full(u)
+        }
+    }
+
+    #[test]
+    fn test_hit_frame() {
+        let hit = HitInfo::new(
+            1.0,
+            Point3::zero(),
+            Vec3::zaxis(),
+            lambertian(Color::full(0.5)),
+            0.0,
+            0.0,
+        );
+        // 接線が分からなければ法線に直交する軸で補う
+        let (dpdu, dpdv) = hit.tangents();
+        assert!(dpdu.dot(hit.n).abs() < 1e-12 && dpdv.dot(hit.n).abs() < 1e-12);
+        assert!(dpdu.dot(dpdv).abs() < 1e-12);
+
+        // x 軸は dpdu をシェーディング法線の接平面に射影した向き
+        let ns = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let hit = hit
+            .with_tangents(Vec3::new(2.0, 1.0, 0.0), Vec3::yaxis())
+            .with_shading_normal(ns);
+        let frame = hit.frame();
+        assert!((frame.w() - ns).near_zero());
+        assert!((frame.u() - Vec3::new(1.0, 1.0, -1.0).normalize()).near_zero());
+        assert!(frame.u().dot(frame.v()).abs() < 1e-12);
+
+        // 幾何法線とシェーディング法線で面の反対側になる向きは漏れる
+        assert!(hit.leaks(Vec3::new(-1.0, 0.0, 0.3)));
+        assert!(hit.leaks(Vec3::new(0.5, 0.0, -0.2)));
+        assert!(!hit.leaks(Vec3::zaxis()));
+        assert!(!hit.leaks(-Vec3::zaxis()));
+    }
+
+    // 平らな法線マップは法線を変えない
+    #[test]
+    fn test_normal_map() {
+        let sphere = Sphere::new(Point3::zero(), 1.0, lambertian(Color::full(0.5)));
+        let flat = Color::new(128.0, 128.0, 255.0) / 255.0;
+        let shape = NormalMap::new(Box::new(sphere), Box::new(ColorTexture::new(flat)));
+        for &origin in &[
+            Point3::new(0.0, 0.0, -5.0),
+            Point3::new(3.0, 4.0, 1.0),
+            Point3::new(-2.0, -1.0, 3.0),
+        ] {
+            let hit = shape
+                .hit(&Ray::new(origin, -origin), 0.001, f64::MAX)
+                .unwrap();
+            assert!((hit.ns - hit.n).length() < 0.01);
+        }
+    }
+
+    // u 方向の坂で、法線が傾き分だけ坂の下に倒れる
+    #[test]
+    fn test_bump_map() {
+        // dpdu の長さは 2 なので、x に対する高さの傾きは 0.5 / 2
+        let rect = Rect::new(
+            0.0,
+            2.0,
+            0.0,
+            1.0,
+            0.0,
+            RectAxisType::XY,
+            lambertian(Color::full(0.5)),
+        );
+        let shape = BumpMap::new(Box::new(rect), Box::new(Ramp), 0.5);
+        let ray = Ray::new(Point3::new(0.7, 0.4, 3.0), -Vec3::zaxis());
+        let hit = shape.hit(&ray, 0.001, f64::MAX).unwrap();
+        let angle = hit.ns.dot(hit.n).acos();
+        assert!((angle - 0.25f64.atan()).abs() < 1e-6, "{}", angle);
+        assert!(hit.ns.x() < 0.0 && hit.ns.y().abs() < 1e-12);
+    }
+
+    // 球の接線は uv の差分と一致する
+    #[test]
+    fn test_sphere_tangents() {
+        let sphere = Sphere::new(
+            Point3::new(1.0, 2.0, 3.0),
+            2.0,
+            lambertian(Color::full(0.5)),
+        );
+        let uv = |p: Point3| Sphere::uv((p - sphere.center).normalize());
+        let h = 1e-6;
+        for &n in &[
+            Vec3::new(0.3, 0.5, 0.8),
+            Vec3::new(-0.6, -0.2, 0.4),
+            Vec3::new(0.1, -0.9, -0.3),
+        ] {
+            let n = n.normalize();
+            let (u, v) = Sphere::uv(n);
+            let (dpdu, dpdv) = sphere.tangents(n);
+            let p = sphere.center + n * sphere.radius;
+            let (u1, v1) = uv(p + dpdu * h);
+            assert!(((u1 - u) / h - 1.0).abs() < 1e-4 && ((v1 - v) / h).abs() < 1e-4);
+            let (u2, v2) = uv(p + dpdv * h);
+            assert!(((u2 - u) / h).abs() < 1e-4 && ((v2 - v) / h - 1.0).abs() < 1e-4);
+        }
+    }
 }
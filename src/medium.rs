@@ -0,0 +1,276 @@
+// 密度が一様な関与媒質 (煙・霧) と位相関数
+//
+// 媒質は境界の形状の内側で、光線が進むごとに一定の確率で散乱する。
+// 散乱した点では位相関数の材質を返すので、積分器からは面と同じように扱える。
+// 散乱するまでの距離は、積分器がサンプラーから取って光線に持たせた種 (Ray::seed) と
+// 媒質ごとの値を混ぜた乱数で決める (重なった媒質どうしで距離が相関しないように混ぜる)。
+// 位相関数の eval は BSDF × |cosθ| の代わりに albedo × 位相関数 (cos はかけない)。
+
+use crate::*;
+
+// 境界の内側で一様な密度の媒質
+// 境界は凸形状に限る (光線が入ってから出るまでを1区間とみなす)
+pub struct ConstantMedium {
+    boundary: Box<dyn Shape>,
+    neg_inv_density: f64,
+    phase: Arc<dyn Material>,
+    id: u64, // 乱数を媒質ごとに変えるための値 (境界と密度から決める)
+}
+
+impl ConstantMedium {
+    // density は正 (0 以下だと距離が負や NaN になり、[t0, t1] の外に当たってしまう)
+    pub fn new(boundary: Box<dyn Shape>, density: f64, phase: Arc<dyn Material>) -> Self {
+        assert!(density > 0.0, "density must be positive, found {}", density);
+        let bbox = boundary.bounding_box();
+        let id = hash_unit(&[
+            bbox.min.x().to_bits(),
+            bbox.min.y().to_bits(),
+            bbox.min.z().to_bits(),
+            bbox.max.x().to_bits(),
+            bbox.max.y().to_bits(),
+            bbox.max.z().to_bits(),
+            density.to_bits(),
+        ])
+        .to_bits();
+        Self {
+            boundary,
+            neg_inv_density: -density.recip(),
+            phase,
+            id,
+        }
+    }
+
+    // 物体 (bbox) と視点を囲む球の中を一様な霧で満たす (外に抜けた光線は背景が見える)
+    pub fn fog(bbox: AABB, lookfrom: Point3, density: f64, phase: Arc<dyn Material>) -> Self {
+        let center = (bbox.min + bbox.max) * 0.5;
+        let radius = (bbox.max - center)
+            .length()
+            .max((lookfrom - center).length())
+            * 1.01;
+        Self::new(
+            Box::new(Sphere::new(center, radius, Arc::clone(&phase))),
+            density,
+            phase,
+        )
+    }
+
+    // 光線とこの媒質で決まる [0, 1) の乱数
+    // 種を持たない光線 (光源の pdf の計算など) は、光線の値から作る
+    fn random(&self, ray: &Ray) -> f64 {
+        match ray.seed {
+            Some(seed) => hash_unit(&[seed, self.id]),
+            None => {
+                let (o, d) = (ray.origin, ray.direction);
+                hash_unit(&[
+                    o.x().to_bits(),
+                    o.y().to_bits(),
+                    o.z().to_bits(),
+                    d.x().to_bits(),
+                    d.y().to_bits(),
+                    d.z().to_bits(),
+                    ray.time.to_bits(),
+                    self.id,
+                ])
+            }
+        }
+    }
+}
+
+impl Shape for ConstantMedium {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        // 光線の始点が内側にあっても入口が見つかるように、後ろ向きにも探す
+        let enter = self.boundary.hit(ray, -f64::MAX, f64::MAX)?;
+        let exit = self.boundary.hit(ray, enter.t + 0.0001, f64::MAX)?;
+        let (enter, exit) = (enter.t.max(t0), exit.t.min(t1));
+        if enter >= exit {
+            return None;
+        }
+
+        // 媒質の中を進む距離は指数分布になる
+        let length = ray.direction.length();
+        let distance = self.neg_inv_density * (1.0 - self.random(ray)).ln();
+        if distance > (exit - enter) * length {
+            return None;
+        }
+
+        // 法線は意味を持たないので適当な向きにする
+        let t = enter + distance / length;
+        Some(HitInfo::new(
+            t,
+            ray.at(t),
+            Vec3::xaxis(),
+            Arc::clone(&self.phase),
+            0.0,
+            0.0,
+        ))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+}
+
+// 全方向に同じ強さで散乱する位相関数
+pub struct Isotropic {
+    albedo: Box<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Box<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::DIFFUSE | Lobe::REFLECTION | Lobe::TRANSMISSION
+    }
+
+    fn eval(&self, hit: &HitInfo, _wi: Vec3, _wo: Vec3) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.p) * (0.25 * FRAC_1_PI)
+    }
+
+    fn sample(&self, hit: &HitInfo, _wo: Vec3, [_, u1, u2]: [f64; 3]) -> Option<BsdfSample> {
+        let cos_theta = 1.0 - 2.0 * u1;
+        let wi = spherical_direction(cos_theta, PI2 * u2, Vec3::zaxis());
+        Some(BsdfSample::new(
+            wi,
+            self.albedo.value(hit.u, hit.v, hit.p),
+            0.25 * FRAC_1_PI,
+            Lobe::DIFFUSE | Lobe::REFLECTION,
+        ))
+    }
+
+    fn pdf(&self, _hit: &HitInfo, _wi: Vec3, _wo: Vec3) -> f64 {
+        0.25 * FRAC_1_PI
+    }
+}
+
+// Henyey-Greenstein の位相関数
+// g ∈ (-1, 1) が正なら前方 (光の進む向き)、負なら後方に多く散乱する。0 なら等方
+pub struct HenyeyGreenstein {
+    albedo: Box<dyn Texture>,
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    // g が ±1 だと1方向にしか散乱しなくなるので少し内側に収める
+    const MAX_G: f64 = 0.999;
+
+    pub fn new(albedo: Box<dyn Texture>, g: f64) -> Self {
+        Self {
+            albedo,
+            g: g.clamp(-Self::MAX_G, Self::MAX_G),
+        }
+    }
+
+    // wo と wi (どちらも散乱点から離れる向き) のなす角の cos に対する値
+    fn phase(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+        0.25 * FRAC_1_PI * (1.0 - g * g) / (denom * denom.max(0.0).sqrt())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::GLOSSY | Lobe::REFLECTION | Lobe::TRANSMISSION
+    }
+
+    fn eval(&self, hit: &HitInfo, wi: Vec3, wo: Vec3) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.p) * self.phase(wi.dot(wo))
+    }
+
+    // 位相関数の分布を逆関数法でサンプリングする (pbrt の式)
+    fn sample(&self, hit: &HitInfo, wo: Vec3, [_, u1, u2]: [f64; 3]) -> Option<BsdfSample> {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u1);
+            -(1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let wi = spherical_direction(cos_theta.clamp(-1.0, 1.0), PI2 * u2, wo);
+        Some(BsdfSample::new(
+            wi,
+            self.albedo.value(hit.u, hit.v, hit.p),
+            self.phase(wi.dot(wo)),
+            Lobe::GLOSSY | Lobe::REFLECTION,
+        ))
+    }
+
+    fn pdf(&self, _hit: &HitInfo, wi: Vec3, wo: Vec3) -> f64 {
+        self.phase(wi.dot(wo))
+    }
+}
+
+// axis とのなす角の cos が cos_theta で、axis まわりの角度が phi の向き
+fn spherical_direction(cos_theta: f64, phi: f64, axis: Vec3) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    ONB::new(axis).local(Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(material: Arc<dyn Material>) -> HitInfo {
+        HitInfo::new(0.0, Point3::zero(), Vec3::zaxis(), material, 0.0, 0.0)
+    }
+
+    // 散乱するまでの距離は光線の種で決まり、平均は 1 / 密度になる
+    #[test]
+    fn test_free_flight() {
+        let white: Arc<dyn Material> =
+            Arc::new(Isotropic::new(Box::new(ColorTexture::new(Color::one()))));
+        let boundary = Sphere::new(Point3::zero(), 1e6, Arc::clone(&white));
+        let medium = ConstantMedium::new(Box::new(boundary), 0.5, white);
+        let ray = Ray::new(Point3::zero(), Vec3::xaxis());
+        let t = |seed| medium.hit(&ray.with_seed(seed), 0.001, f64::MAX).unwrap().t;
+        assert_eq!(t(1), t(1));
+        assert_ne!(t(1), t(2));
+
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let n = 20000;
+        let mean = (0..n).map(|_| t(sampler.next_f64().to_bits())).sum::<f64>() / n as f64;
+        assert!((mean - 2.0).abs() < 0.05, "{}", mean);
+    }
+
+    // 位相関数は球面全体で積分すると 1 になり、サンプルの pdf と一致する
+    // 光の進む向き (-wo) と wi のなす角の cos の平均が g になる
+    #[test]
+    fn test_phase() {
+        let white = || -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::one())) };
+        let materials: Vec<(Arc<dyn Material>, f64)> = vec![
+            (Arc::new(Isotropic::new(white())), 0.0),
+            (Arc::new(HenyeyGreenstein::new(white(), 0.7)), 0.7),
+            (Arc::new(HenyeyGreenstein::new(white(), -0.4)), -0.4),
+        ];
+        let wo = Vec3::new(0.3, -0.5, 0.8).normalize();
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let n = 20000;
+        for (material, g) in materials {
+            let hit = hit(material);
+            let mut integral = 0.0;
+            let mut mean_cos = 0.0;
+            for _ in 0..n {
+                let u = [sampler.next_f64(), sampler.next_f64(), sampler.next_f64()];
+                let sample = hit.m.sample(&hit, wo, u).unwrap();
+                let pdf = hit.m.pdf(&hit, sample.wi, wo);
+                assert!((sample.pdf - pdf).abs() < 1e-9 * pdf);
+                assert!((sample.weight - Color::one()).near_zero());
+                mean_cos -= sample.wi.dot(wo) / n as f64;
+
+                let [u1, u2] = sampler.next_2d();
+                let w = spherical_direction(1.0 - 2.0 * u1, PI2 * u2, Vec3::zaxis());
+                integral += hit.m.eval(&hit, w, wo).x() * 4.0 * PI / n as f64;
+            }
+            assert!((integral - 1.0).abs() < 0.05, "{}", integral);
+            assert!((mean_cos - g).abs() < 0.02, "{} {}", mean_cos, g);
+        }
+    }
+}
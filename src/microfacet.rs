@@ -0,0 +1,562 @@
+// GGX (Trowbridge-Reitz) のマイクロファセットによる金属と粗いガラス
+//
+// 局所座標は法線を z 軸にとり、wo (視線の逆向き) と wi (散乱後の向き) はどちらも面から離れる向き。
+// 微小面の法線は見えている面だけからサンプリングする (Heitz 2018, VNDF)。
+
+use crate::*;
+use std::str::FromStr;
+
+// これより滑らかな面は鏡面反射・屈折として扱う
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    alpha_x: f64, // 局所座標の x 方向の粗さ
+    alpha_y: f64,
+}
+
+impl Ggx {
+    // roughness は見た目の粗さ (alpha = roughness²)
+    pub fn new(roughness: f64) -> Self {
+        Self::anisotropic(roughness, 0.0)
+    }
+
+    // anisotropic ∈ [0, 1] が大きいほど x 方向に伸びたハイライトになる
+    pub fn anisotropic(roughness: f64, anisotropic: f64) -> Self {
+        let alpha = roughness.max(0.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropic.clamp(0.0, 1.0)).sqrt();
+        Self {
+            alpha_x: alpha / aspect,
+            alpha_y: alpha * aspect,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    // 法線分布関数
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z() <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = (self.alpha_x, self.alpha_y);
+        let t = (h.x() / ax).powi(2) + (h.y() / ay).powi(2) + h.z() * h.z();
+        1.0 / (PI * ax * ay * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        // 向きに応じた粗さ² × tan²θ
+        let a2tan2 = ((self.alpha_x * w.x()).powi(2) + (self.alpha_y * w.y()).powi(2)) / cos2;
+        ((1.0 + a2tan2).sqrt() - 1.0) * 0.5
+    }
+
+    // Smith の遮蔽関数 (片側)
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // 高さ相関の Smith の遮蔽・マスキング関数
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // wo から見える微小面の法線 (wo.z > 0)
+    pub fn sample_visible(&self, wo: Vec3, [u1, u2]: [f64; 2]) -> Vec3 {
+        // alpha = 1 に引き伸ばした半球の上で考える
+        let vh = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).normalize();
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+        } else {
+            Vec3::xaxis()
+        };
+        let t2 = vh.cross(t1);
+        let r = u1.sqrt();
+        let (sin_phi, cos_phi) = (PI2 * u2).sin_cos();
+        let p1 = r * cos_phi;
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * sin_phi;
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .normalize()
+    }
+
+    // sample_visible で h が選ばれる確率密度
+    pub fn visible_pdf(&self, wo: Vec3, h: Vec3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z()
+    }
+
+    // 見える微小面で鏡面反射した向き (面の下に潜ることもある)
+    pub fn sample_reflection(&self, wo: Vec3, u: [f64; 2]) -> Vec3 {
+        let h = self.sample_visible(wo, u);
+        (-wo).reflect(h)
+    }
+
+    // sample_reflection で wi が選ばれる確率密度
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        self.visible_pdf(wo, h) / (4.0 * wo.dot(h).abs())
+    }
+
+    // 見える誘電体の微小面で、フレネル反射率の確率で反射、残りで屈折した向き
+    // (u[0] で反射か屈折かを選ぶ)
+    pub fn sample_dielectric(&self, wo: Vec3, eta: f64, [u0, u1, u2]: [f64; 3]) -> Vec3 {
+        let h = self.sample_visible(wo, [u1, u2]);
+        let f = fresnel_dielectric(wo.dot(h), eta);
+        match wo.refract(h, eta.recip()) {
+            Some(refracted) if u0 >= f => refracted,
+            _ => (-wo).reflect(h),
+        }
+    }
+
+    // sample_dielectric で wi が選ばれる確率密度
+    pub fn dielectric_pdf(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        match dielectric_half_vector(wo, wi, eta) {
+            Some((h, true)) => {
+                let f = fresnel_dielectric(wo.dot(h), eta);
+                f * self.visible_pdf(wo, h) / (4.0 * wo.dot(h))
+            }
+            Some((h, false)) => {
+                let (doh, dih) = (wo.dot(h), wi.dot(h));
+                let f = fresnel_dielectric(doh, eta);
+                let denom = doh + eta * dih;
+                (1.0 - f) * self.visible_pdf(wo, h) * eta * eta * dih.abs() / (denom * denom)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+// 誘電体のフレネル反射率 (eta は入射側に対する透過側の屈折率の比、全反射なら 1)
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// 複素屈折率 eta + ik の導体のフレネル反射率 (RGB ごと)
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let f = |eta: f64, k: f64| {
+        let (eta2, k2) = (eta * eta, k * k);
+        let t0 = eta2 - k2 - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+        let t1 = a2b2 + cos2;
+        let t2 = 2.0 * cos2.sqrt() * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Color::new(f(eta.x(), k.x()), f(eta.y(), k.y()), f(eta.z(), k.z()))
+}
+
+// 代表的な金属の複素屈折率 (RGB の代表波長での値)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    pub const NAMES: &'static [&'static str] = &["gold", "copper", "aluminium", "silver"];
+
+    // (eta, k)
+    pub fn ior(&self) -> (Color, Color) {
+        match self {
+            ConductorPreset::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            ConductorPreset::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            ConductorPreset::Aluminium => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+            ConductorPreset::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+}
+
+impl FromStr for ConductorPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gold" => Ok(ConductorPreset::Gold),
+            "copper" => Ok(ConductorPreset::Copper),
+            "aluminium" | "aluminum" => Ok(ConductorPreset::Aluminium),
+            "silver" => Ok(ConductorPreset::Silver),
+            _ => Err(format!(
+                "unknown conductor {:?} (expected one of: {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// 裏から当たった場合に z を反転して、wo が表側 (z > 0) に来るようにする
+pub fn flip_z(v: Vec3, flip: bool) -> Vec3 {
+    if flip {
+        Vec3::new(v.x(), v.y(), -v.z())
+    } else {
+        v
+    }
+}
+
+// 粗い金属
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    ggx: Ggx,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            eta,
+            k,
+            ggx: Ggx::new(roughness),
+        }
+    }
+
+    pub fn preset(preset: ConductorPreset, roughness: f64) -> Self {
+        let (eta, k) = preset.ior();
+        Self::new(eta, k, roughness)
+    }
+}
+
+impl Material for Conductor {
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        if self.ggx.is_smooth() {
+            Lobe::SPECULAR | Lobe::REFLECTION
+        } else {
+            Lobe::GLOSSY | Lobe::REFLECTION
+        }
+    }
+
+    fn eval(&self, _hit: &HitInfo, wi: Vec3, wo: Vec3) -> Color {
+        if self.ggx.is_smooth() || !same_hemisphere(wi, wo) {
+            return Color::zero();
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (flip_z(wo, flip), flip_z(wi, flip));
+        let h = (wo + wi).normalize();
+        let f = fresnel_conductor(wo.dot(h), self.eta, self.k);
+        f * (self.ggx.d(h) * self.ggx.g(wo, wi) / (4.0 * wo.z()))
+    }
+
+    fn sample(&self, hit: &HitInfo, wo: Vec3, [_, u1, u2]: [f64; 3]) -> Option<BsdfSample> {
+        if self.ggx.is_smooth() {
+            let albedo = fresnel_conductor(wo.z().abs(), self.eta, self.k);
+            return Some(BsdfSample::specular(
+                reflect_local(wo),
+                albedo,
+                Lobe::REFLECTION,
+            ));
+        }
+        let flip = wo.z() < 0.0;
+        let wi = flip_z(self.ggx.sample_reflection(flip_z(wo, flip), [u1, u2]), flip);
+        let pdf = self.pdf(hit, wi, wo);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(
+            wi,
+            self.eval(hit, wi, wo) / pdf,
+            pdf,
+            Lobe::GLOSSY | Lobe::REFLECTION,
+        ))
+    }
+
+    fn pdf(&self, _hit: &HitInfo, wi: Vec3, wo: Vec3) -> f64 {
+        if self.ggx.is_smooth() || !same_hemisphere(wi, wo) {
+            return 0.0;
+        }
+        let flip = wo.z() < 0.0;
+        self.ggx.reflection_pdf(flip_z(wo, flip), flip_z(wi, flip))
+    }
+}
+
+// 粗いガラス
+pub struct RoughDielectric {
+    ri: f64, // 屈折率
+    ggx: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(ri: f64, roughness: f64) -> Self {
+        Self {
+            ri,
+            ggx: Ggx::new(roughness),
+        }
+    }
+
+    // 透過側の屈折率の比 (wo.z > 0 なら外から入る)
+    fn eta(&self, wo: Vec3) -> f64 {
+        if wo.z() > 0.0 {
+            self.ri
+        } else {
+            self.ri.recip()
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        let lobe = Lobe::REFLECTION | Lobe::TRANSMISSION;
+        if self.ggx.is_smooth() {
+            Lobe::SPECULAR | lobe
+        } else {
+            Lobe::GLOSSY | lobe
+        }
+    }
+
+    fn eval(&self, _hit: &HitInfo, wi: Vec3, wo: Vec3) -> Color {
+        if self.ggx.is_smooth() {
+            return Color::zero();
+        }
+        let eta = self.eta(wo);
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (flip_z(wo, flip), flip_z(wi, flip));
+        let value = match dielectric_half_vector(wo, wi, eta) {
+            Some((h, true)) => {
+                let f = fresnel_dielectric(wo.dot(h), eta);
+                f * self.ggx.d(h) * self.ggx.g(wo, wi) / (4.0 * wo.z())
+            }
+            Some((h, false)) => {
+                // 放射輝度を運ぶので屈折率の比の2乗は打ち消し合う
+                let (doh, dih) = (wo.dot(h), wi.dot(h));
+                let f = fresnel_dielectric(doh, eta);
+                let denom = doh + eta * dih;
+                (1.0 - f) * self.ggx.d(h) * self.ggx.g(wo, wi) * (doh * dih).abs()
+                    / (wo.z() * denom * denom)
+            }
+            None => 0.0,
+        };
+        Color::full(value)
+    }
+
+    fn sample(&self, hit: &HitInfo, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let eta = self.eta(wo);
+        let flip = wo.z() < 0.0;
+        let upper = flip_z(wo, flip);
+        if self.ggx.is_smooth() {
+            // 粗い場合と同じく、屈折で放射輝度は 1/屈折率² になる
+            return Some(match upper.refract(Vec3::zaxis(), eta.recip()) {
+                Some(refracted) if u[0] >= fresnel_dielectric(upper.z(), eta) => {
+                    BsdfSample::specular(
+                        flip_z(refracted.normalize(), flip),
+                        Color::full(eta.powi(-2)),
+                        Lobe::TRANSMISSION,
+                    )
+                }
+                _ => BsdfSample::specular(reflect_local(wo), Color::one(), Lobe::REFLECTION),
+            });
+        }
+        let wi = flip_z(self.ggx.sample_dielectric(upper, eta, u), flip);
+        let pdf = self.pdf(hit, wi, wo);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let lobe = if same_hemisphere(wi, wo) {
+            Lobe::REFLECTION
+        } else {
+            Lobe::TRANSMISSION
+        };
+        Some(BsdfSample::new(
+            wi,
+            self.eval(hit, wi, wo) / pdf,
+            pdf,
+            Lobe::GLOSSY | lobe,
+        ))
+    }
+
+    fn pdf(&self, _hit: &HitInfo, wi: Vec3, wo: Vec3) -> f64 {
+        if self.ggx.is_smooth() {
+            return 0.0;
+        }
+        let eta = self.eta(wo);
+        let flip = wo.z() < 0.0;
+        self.ggx
+            .dielectric_pdf(flip_z(wo, flip), flip_z(wi, flip), eta)
+    }
+}
+
+// 反射・屈折の方向から微小面の法線を求める ((h, 反射か))
+pub fn dielectric_half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<(Vec3, bool)> {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return None;
+    }
+    let reflect = wi.z() > 0.0;
+    let h = if reflect { wo + wi } else { wo + eta * wi };
+    if h.near_zero() {
+        return None;
+    }
+    let h = h.normalize();
+    let h = if h.z() < 0.0 { -h } else { h };
+    // 屈折は wo と wi が微小面の反対側にある場合だけ
+    if wo.dot(h) <= 0.0 || (!reflect && wi.dot(h) >= 0.0) {
+        return None;
+    }
+    Some((h, reflect))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fresnel() {
+        // 垂直入射は ((1 - eta) / (1 + eta))²
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        // 臨界角を超えると全反射
+        assert_eq!(1.0, fresnel_dielectric(0.1, 1.5f64.recip()));
+        assert!((fresnel_dielectric(0.0, 1.5) - 1.0).abs() < 1e-12);
+        // 消衰係数が 0 の導体は誘電体と同じ
+        for &cos in &[1.0, 0.7, 0.2] {
+            let f = fresnel_conductor(cos, Color::full(1.5), Color::zero());
+            assert!((f.x() - fresnel_dielectric(cos, 1.5)).abs() < 1e-9);
+        }
+        // 金は赤が青より強く反射する
+        let (eta, k) = ConductorPreset::Gold.ior();
+        let f = fresnel_conductor(1.0, eta, k);
+        assert!(f.x() > 0.9 && f.x() > f.z());
+    }
+
+    // pdf を球面で積分すると、sample が散乱した割合になる
+    // (微小面で反射した向きが面の下に潜ると散乱しない)
+    #[test]
+    fn test_pdf() {
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let hit = HitInfo::new(
+            1.0,
+            Point3::zero(),
+            Vec3::zaxis(),
+            Arc::new(RoughDielectric::new(1.5, 0.5)),
+            0.0,
+            0.0,
+        );
+        let cases: [(Box<dyn Material>, Vec3); 3] = [
+            (
+                Box::new(Conductor::preset(ConductorPreset::Gold, 0.5)),
+                Vec3::new(0.6, 0.0, 0.8),
+            ),
+            (
+                Box::new(RoughDielectric::new(1.5, 0.5)),
+                Vec3::new(0.6, 0.0, 0.8),
+            ),
+            // 中から当たる
+            (
+                Box::new(RoughDielectric::new(1.5, 0.5)),
+                Vec3::new(0.0, 0.6, -0.8),
+            ),
+        ];
+        for (material, wo) in cases.iter() {
+            let n = 4000;
+            let mut lost = 0;
+            for _ in 0..n {
+                let u = [sampler.next_f64(), sampler.next_f64(), sampler.next_f64()];
+                match material.sample(&hit, *wo, u) {
+                    Some(sample) => {
+                        let pdf = material.pdf(&hit, sample.wi, *wo);
+                        assert!((sample.pdf - pdf).abs() <= 1e-9 * pdf);
+                        assert!(!sample.lobe.is_specular());
+                    }
+                    None => lost += 1,
+                }
+            }
+            let lost = lost as f64 / n as f64;
+            // 球面を θ, φ の格子に分けて積分する
+            let (nt, np) = (400, 800);
+            let (dt, dp) = (PI / nt as f64, PI2 / np as f64);
+            let mut integral = 0.0;
+            for i in 0..nt {
+                let (sin_t, cos_t) = ((i as f64 + 0.5) * dt).sin_cos();
+                for j in 0..np {
+                    let (sin_p, cos_p) = ((j as f64 + 0.5) * dp).sin_cos();
+                    let wi = Vec3::new(sin_t * cos_p, sin_t * sin_p, cos_t);
+                    integral += material.pdf(&hit, wi, *wo) * sin_t * dt * dp;
+                }
+            }
+            assert!(lost < 0.1);
+            assert!((integral + lost - 1.0).abs() < 0.03);
+        }
+    }
+
+    // 滑らかな場合の透過の重みは、粗さを 0 に近づけた極限と一致する
+    #[test]
+    fn test_smooth_transmission() {
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let hit = HitInfo::new(
+            1.0,
+            Point3::zero(),
+            Vec3::zaxis(),
+            Arc::new(RoughDielectric::new(1.5, 0.0)),
+            0.0,
+            0.0,
+        );
+        for &(wo, eta) in &[
+            (Vec3::new(0.0, 0.0, 1.0), 1.5),
+            (Vec3::new(0.0, 0.0, -1.0), 1.5f64.recip()),
+        ] {
+            let smooth = RoughDielectric::new(1.5, 0.0);
+            let sample = smooth.sample(&hit, wo, [0.99, 0.5, 0.5]).unwrap();
+            assert!(sample.lobe.contains(Lobe::TRANSMISSION));
+            assert!((sample.weight - Color::full(eta.powi(-2))).near_zero());
+
+            let rough = RoughDielectric::new(1.5, 0.05);
+            let (mut sum, mut count) = (0.0, 0);
+            for _ in 0..4000 {
+                let u = [sampler.next_f64(), sampler.next_f64(), sampler.next_f64()];
+                if let Some(sample) = rough.sample(&hit, wo, u) {
+                    if sample.lobe.contains(Lobe::TRANSMISSION) {
+                        sum += sample.weight.x();
+                        count += 1;
+                    }
+                }
+            }
+            let mean = sum / count as f64;
+            assert!((mean * eta * eta - 1.0).abs() < 0.05, "{}", mean);
+        }
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Ok(ConductorPreset::Aluminium), "aluminum".parse());
+        assert!("brass".parse::<ConductorPreset>().is_err());
+    }
+}
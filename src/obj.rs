@@ -0,0 +1,615 @@
+// Wavefront OBJ / MTL の読み込み
+//
+// 対応している要素
+//   OBJ: v, vn, vt, f (多角形は三角形に分割), g, o, usemtl, mtllib
+//   MTL: newmtl, Kd, Ks, Ns, Ni, Ke, d, Tr, illum, map_Kd
+
+use crate::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+// 同じ名前 (g/o) と材質を持つ面の集まり
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Option<String>,
+    faces: Vec<Face>,
+}
+
+pub struct ObjModel {
+    mesh: Arc<MeshData>,
+    pub groups: Vec<ObjGroup>,
+    materials: HashMap<String, Arc<dyn Material>>,
+}
+
+impl ObjModel {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.into(), e))?;
+        Self::parse(&src, path)
+    }
+
+    // path はエラーの表示と mtllib の相対パスの基準に使う
+    pub fn parse(src: &str, path: &Path) -> Result<Self, ObjError> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut parser = ObjParser::new(path);
+        for (i, line) in src.lines().enumerate() {
+            parser.line = i + 1;
+            parser.parse_line(line, dir)?;
+        }
+        let model = parser.finish();
+        if model.groups.is_empty() {
+            return Err(ObjError::Parse {
+                path: path.into(),
+                line: src.lines().count(),
+                message: String::from("no faces"),
+            });
+        }
+        Ok(model)
+    }
+
+    // 材質ごとに TriangleMesh を作る
+    // material を指定した場合は MTL の材質より優先する
+    pub fn into_shape(self, material: Option<Arc<dyn Material>>) -> Box<dyn Shape> {
+        let default: Arc<dyn Material> = material.clone().unwrap_or_else(|| {
+            Arc::new(Lambertian::new(Box::new(ColorTexture::new(Color::full(
+                0.8,
+            )))))
+        });
+
+        let mut shapes = ShapeList::new();
+        for group in self.groups {
+            let m = match (&material, &group.material) {
+                (None, Some(name)) => self.materials.get(name).cloned(),
+                _ => None,
+            }
+            .unwrap_or_else(|| Arc::clone(&default));
+            shapes.push(Box::new(TriangleMesh::new(
+                Arc::clone(&self.mesh),
+                group.faces,
+                m,
+            )));
+        }
+
+        if shapes.objects.len() == 1 {
+            shapes.objects.pop().unwrap()
+        } else {
+            Box::new(BvhNode::from_list(shapes))
+        }
+    }
+}
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    line: usize,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+    groups: Vec<ObjGroup>,
+    materials: HashMap<String, Arc<dyn Material>>,
+}
+
+impl<'a> ObjParser<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            line: 0,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            groups: vec![ObjGroup {
+                name: String::from("default"),
+                material: None,
+                faces: Vec::new(),
+            }],
+            materials: HashMap::new(),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse {
+            path: self.path.into(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn finish(self) -> ObjModel {
+        ObjModel {
+            mesh: Arc::new(MeshData::new(self.positions, self.normals, self.uvs)),
+            groups: self
+                .groups
+                .into_iter()
+                .filter(|g| !g.faces.is_empty())
+                .collect(),
+            materials: self.materials,
+        }
+    }
+
+    // 新しいグループを始める (名前・材質は直前のグループを引き継ぐ)
+    fn begin_group(&mut self, name: Option<&str>, material: Option<&str>) {
+        let last = self.groups.last().unwrap();
+        let group = ObjGroup {
+            name: name.map_or_else(|| last.name.clone(), String::from),
+            material: material.map_or_else(|| last.material.clone(), |m| Some(m.to_string())),
+            faces: Vec::new(),
+        };
+        self.groups.push(group);
+    }
+
+    fn parse_line(&mut self, line: &str, dir: &Path) -> Result<(), ObjError> {
+        let line = line.split('#').next().unwrap();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let args = tokens.collect::<Vec<_>>();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = self.floats::<3>(&args)?;
+                self.positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = self.floats::<3>(&args)?;
+                self.normals.push(Vec3::new(x, y, z).normalize());
+            }
+            "vt" => {
+                let [u, v] = self.floats::<2>(&args)?;
+                self.uvs.push([u, v]);
+            }
+            "f" => self.face(&args)?,
+            "g" | "o" => self.begin_group(Some(&args.join(" ")), None),
+            "usemtl" => self.begin_group(None, Some(&args.join(" "))),
+            // MTL ファイルが読めなければ、その材質を使う面は既定の材質で描く
+            "mtllib" => {
+                for name in args {
+                    match read_mtl(&dir.join(name)) {
+                        Ok(materials) => self.materials.extend(materials),
+                        Err(e @ ObjError::Io(..)) => {
+                            eprintln!("warning: {} (using the default material)", e)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            // スムージンググループ・線・点などは無視する
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // 先頭の N 個の数値 (余分な w 成分などは無視する)
+    fn floats<const N: usize>(&self, args: &[&str]) -> Result<[f64; N], ObjError> {
+        if args.len() < N {
+            return Err(self.error(format!("expected {} numbers", N)));
+        }
+        let mut values = [0.0; N];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = arg
+                .parse()
+                .map_err(|_| self.error(format!("invalid number {:?}", arg)))?;
+        }
+        Ok(values)
+    }
+
+    // 1始まり・負数は末尾からの相対インデックス
+    fn index(&self, s: &str, len: usize) -> Result<usize, ObjError> {
+        let i = s
+            .parse::<i64>()
+            .map_err(|_| self.error(format!("invalid index {:?}", s)))?;
+        let index = if i < 0 { len as i64 + i } else { i - 1 };
+        if index < 0 || index >= len as i64 {
+            return Err(self.error(format!("index {} out of range", i)));
+        }
+        Ok(index as usize)
+    }
+
+    // v, v/vt, v//vn, v/vt/vn
+    fn face(&mut self, args: &[&str]) -> Result<(), ObjError> {
+        if args.len() < 3 {
+            return Err(self.error("face needs at least 3 vertices"));
+        }
+
+        let mut v = Vec::with_capacity(args.len());
+        let mut vt = Vec::with_capacity(args.len());
+        let mut vn = Vec::with_capacity(args.len());
+        for arg in args {
+            let mut parts = arg.split('/');
+            v.push(self.index(parts.next().unwrap(), self.positions.len())?);
+            match parts.next() {
+                Some("") | None => {}
+                Some(s) => vt.push(self.index(s, self.uvs.len())?),
+            }
+            match parts.next() {
+                Some("") | None => {}
+                Some(s) => vn.push(self.index(s, self.normals.len())?),
+            }
+        }
+        // 一部の頂点だけに法線・テクスチャ座標がある場合は使わない
+        let has_vt = vt.len() == v.len();
+        let has_vn = vn.len() == v.len();
+
+        let points = v.iter().map(|&i| self.positions[i]).collect::<Vec<_>>();
+        let faces = &mut self.groups.last_mut().unwrap().faces;
+        for [a, b, c] in triangulate(&points) {
+            faces.push(Face {
+                v: [v[a], v[b], v[c]],
+                vn: if has_vn {
+                    Some([vn[a], vn[b], vn[c]])
+                } else {
+                    None
+                },
+                vt: if has_vt {
+                    Some([vt[a], vt[b], vt[c]])
+                } else {
+                    None
+                },
+            });
+        }
+        Ok(())
+    }
+}
+
+// 多角形を耳刈り取り法で三角形に分割する (結果は points のインデックス)
+fn triangulate(points: &[Point3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell 法で法線を求め、最も大きい成分の軸を落として2次元に投影する
+    let normal = (0..n).fold(Vec3::zero(), |acc, i| {
+        let (p, q) = (points[i], points[(i + 1) % n]);
+        acc + Vec3::new(
+            (p.y() - q.y()) * (p.z() + q.z()),
+            (p.z() - q.z()) * (p.x() + q.x()),
+            (p.x() - q.x()) * (p.y() + q.y()),
+        )
+    });
+    let [nx, ny, nz] = normal.to_array();
+    let (ax, ay, sign) = if nx.abs() > ny.abs() && nx.abs() > nz.abs() {
+        (1, 2, nx.signum())
+    } else if ny.abs() > nz.abs() {
+        (2, 0, ny.signum())
+    } else {
+        (0, 1, nz.signum())
+    };
+    let p2 = points
+        .iter()
+        .map(|p| {
+            let a = p.to_array();
+            [a[ax], a[ay] * sign]
+        })
+        .collect::<Vec<_>>();
+
+    let cross = |o: [f64; 2], a: [f64; 2], b: [f64; 2]| {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    };
+
+    let mut remaining = (0..n).collect::<Vec<_>>();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + m - 1) % m],
+                remaining[i],
+                remaining[(i + 1) % m],
+            );
+            if cross(p2[a], p2[b], p2[c]) <= 0.0 {
+                return false; // 凹頂点
+            }
+            // 他の頂点が三角形の内側にあれば耳ではない
+            remaining.iter().all(|&j| {
+                j == a
+                    || j == b
+                    || j == c
+                    || cross(p2[a], p2[b], p2[j]) < 0.0
+                    || cross(p2[b], p2[c], p2[j]) < 0.0
+                    || cross(p2[c], p2[a], p2[j]) < 0.0
+            })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + m - 1) % m],
+                    remaining[i],
+                    remaining[(i + 1) % m],
+                ]);
+                remaining.remove(i);
+            }
+            None => {
+                // 自己交差・縮退した多角形は扇形に分割する
+                for i in 1..remaining.len() - 1 {
+                    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+                }
+                return triangles;
+            }
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+#[derive(Default)]
+struct MtlParams {
+    kd: Option<Color>,
+    ks: Option<Color>,
+    ke: Option<Color>,
+    ns: Option<f64>,
+    ni: Option<f64>,
+    dissolve: Option<f64>,
+    illum: Option<u32>,
+    map_kd: Option<PathBuf>,
+}
+
+impl MtlParams {
+    // MTL のパラメーターを既存の材質に対応づける
+    fn to_material(&self) -> Result<Arc<dyn Material>, image::ImageError> {
+        let kd = self.kd.unwrap_or_else(|| Color::full(0.8));
+        let ks = self.ks.unwrap_or_else(Color::zero);
+        let max = |c: Color| c.iter().fold(0.0_f64, |acc, x| acc.max(*x));
+
+        if let Some(ke) = self.ke.filter(|ke| max(*ke) > 0.0) {
+            return Ok(Arc::new(DiffuseLight::new(Box::new(ColorTexture::new(ke)))));
+        }
+
+        let transparent = self.dissolve.is_some_and(|d| d < 1.0);
+        if transparent || matches!(self.illum, Some(4) | Some(6) | Some(7) | Some(9)) {
+            return Ok(Arc::new(Dielectric::new(self.ni.unwrap_or(1.5))));
+        }
+
+        let texture: Box<dyn Texture> = match &self.map_kd {
+            Some(path) => Box::new(ImageTexture::open(path)?),
+            None => Box::new(ColorTexture::new(kd)),
+        };
+        // Phong の指数を GGX の α (Beckmann 分布の近似) に換算する
+        let alpha = (2.0 / (self.ns.unwrap_or(0.0) + 2.0)).sqrt().min(1.0);
+        if matches!(self.illum, Some(3) | Some(5) | Some(8)) || (max(kd) == 0.0 && max(ks) > 0.0) {
+            // α を反射のずれ度合いとみなす
+            let fuzz = alpha;
+            let albedo: Box<dyn Texture> = if self.map_kd.is_some() {
+                texture
+            } else {
+                Box::new(ColorTexture::new(ks))
+            };
+            return Ok(Arc::new(Metal::new(albedo, fuzz)));
+        }
+        // 拡散 + ハイライトは principled の誘電体にする
+        // (Ks = 0.5 が屈折率 1.5 相当の反射率 0.04 になるように specular に対応づける)
+        if self.illum == Some(2) && max(ks) > 0.0 {
+            let constant =
+                |x: f64| -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::full(x))) };
+            return Ok(Arc::new(
+                Principled::new(texture)
+                    .roughness(constant(alpha.sqrt()))
+                    .specular(constant(max(ks).min(1.0))),
+            ));
+        }
+
+        Ok(Arc::new(Lambertian::new(texture)))
+    }
+}
+
+fn read_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let src = std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.into(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let error = |line: usize, message: String| ObjError::Parse {
+        path: path.into(),
+        line,
+        message,
+    };
+
+    let mut params: Vec<(String, usize, MtlParams)> = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.split('#').next().unwrap();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args = tokens.collect::<Vec<_>>();
+
+        if keyword == "newmtl" {
+            params.push((args.join(" "), line_no, MtlParams::default()));
+            continue;
+        }
+        let current = match params.last_mut() {
+            Some((_, _, current)) => current,
+            None => return Err(error(line_no, format!("{} before newmtl", keyword))),
+        };
+
+        let float = |i: usize| -> Result<f64, ObjError> {
+            let arg = args
+                .get(i)
+                .ok_or_else(|| error(line_no, format!("{} needs a value", keyword)))?;
+            arg.parse()
+                .map_err(|_| error(line_no, format!("invalid number {:?}", arg)))
+        };
+        // "Kd r" のように1つだけの場合は灰色
+        let color = || -> Result<Color, ObjError> {
+            let r = float(0)?;
+            if args.len() >= 3 {
+                Ok(Color::new(r, float(1)?, float(2)?))
+            } else {
+                Ok(Color::full(r))
+            }
+        };
+
+        match keyword {
+            "Kd" => current.kd = Some(color()?),
+            "Ks" => current.ks = Some(color()?),
+            "Ke" => current.ke = Some(color()?),
+            "Ns" => current.ns = Some(float(0)?),
+            "Ni" => current.ni = Some(float(0)?),
+            "d" => current.dissolve = Some(float(0)?),
+            "Tr" => current.dissolve = Some(1.0 - float(0)?),
+            "illum" => current.illum = Some(float(0)? as u32),
+            // オプション (-s など) は無視して最後の引数をファイル名とみなす
+            "map_Kd" => current.map_kd = args.last().map(|name| dir.join(name)),
+            _ => {}
+        }
+    }
+
+    let mut materials = HashMap::new();
+    for (name, line_no, p) in params {
+        let material = p
+            .to_material()
+            .map_err(|e| error(line_no, format!("material {:?}: {}", name, e)))?;
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> ObjModel {
+        ObjModel::parse(src, Path::new("missing/model.obj")).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let model = parse(
+            "mtllib missing.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+g quad
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/3/1
+usemtl blue
+f -4//-1 -3//-1 -2//-1
+",
+        );
+        let mesh = &model.mesh;
+        assert_eq!(
+            (4, 3, 1),
+            (mesh.positions.len(), mesh.uvs.len(), mesh.normals.len())
+        );
+        assert_eq!(2, model.groups.len());
+
+        let red = &model.groups[0];
+        assert_eq!(
+            ("quad", Some("red")),
+            (red.name.as_str(), red.material.as_deref())
+        );
+        assert_eq!(2, red.faces.len());
+        assert_eq!(Some([0, 0, 0]), red.faces[0].vn);
+        assert!(red.faces.iter().all(|f| f.vt.is_some()));
+
+        // 負のインデックスは末尾からの相対位置
+        let blue = &model.groups[1];
+        assert_eq!(Some("blue"), blue.material.as_deref());
+        assert_eq!([0, 1, 2], blue.faces[0].v);
+        assert_eq!(Some([0, 0, 0]), blue.faces[0].vn);
+        assert_eq!(None, blue.faces[0].vt);
+
+        // MTL が読めなくても既定の材質で形状を作れる
+        assert!(model.materials.is_empty());
+        model.into_shape(None);
+
+        assert!(ObjModel::parse("v 0 0 0\nf 1 2 3\n", Path::new("a.obj")).is_err());
+    }
+
+    #[test]
+    fn test_mtl_material() {
+        let hit = HitInfo::new(
+            1.0,
+            Point3::zero(),
+            Vec3::zaxis(),
+            Arc::new(Dielectric::new(1.5)),
+            0.0,
+            0.0,
+        );
+        let lobes = |params: MtlParams| params.to_material().unwrap().lobes(&hit);
+        let plastic = MtlParams {
+            kd: Some(Color::full(0.5)),
+            ks: Some(Color::full(0.5)),
+            ns: Some(100.0),
+            illum: Some(2),
+            ..MtlParams::default()
+        };
+        assert!(lobes(plastic).contains(Lobe::GLOSSY));
+        let mirror = MtlParams {
+            ks: Some(Color::full(0.9)),
+            ns: Some(1000.0),
+            illum: Some(3),
+            ..MtlParams::default()
+        };
+        assert!(lobes(mirror).is_specular());
+        let matte = MtlParams {
+            kd: Some(Color::full(0.5)),
+            illum: Some(2),
+            ..MtlParams::default()
+        };
+        assert_eq!(Lobe::DIFFUSE | Lobe::REFLECTION, lobes(matte));
+    }
+
+    #[test]
+    fn test_triangulate_convex() {
+        let quad = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        assert_eq!(2, triangulate(&quad).len());
+    }
+
+    #[test]
+    fn test_triangulate_concave() {
+        // L字型 (頂点3が凹)
+        let l = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(2.0, 1.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(1.0, 2.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+        ];
+        let triangles = triangulate(&l);
+        assert_eq!(4, triangles.len());
+
+        // 分割後の面積の合計が元の多角形と一致する
+        let area = triangles.iter().fold(0.0, |acc, [a, b, c]| {
+            acc + (l[*b] - l[*a]).cross(l[*c] - l[*a]).length() * 0.5
+        });
+        assert!((area - 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,399 @@
+// Disney の principled BSDF (Burley 2012/2015 を簡略化したもの)
+//
+// 拡散 (と布のような縁の光沢 sheen)、GGX の鏡面反射、クリアコート、粗いガラスの透過を重ねる。
+// パラメーターはどれもテクスチャで変えられる (色以外のパラメーターは R を使う)。
+// 散乱の向きは、見る向きでの各成分の強さに比例した確率で成分を選んでサンプリングする。
+// 異方性のハイライトは、衝突位置の接線 dpdu (テクスチャの u 方向) に沿って伸びる。
+
+use crate::*;
+
+// クリアコートは粗さを固定した GGX で近似する
+const CLEARCOAT_ROUGHNESS: f64 = 0.25;
+// 粗さ 0 で分布が鏡面に退化しないようにする
+const MIN_ROUGHNESS: f64 = 0.03;
+
+pub struct Principled {
+    base_color: Box<dyn Texture>,
+    metallic: Box<dyn Texture>,
+    roughness: Box<dyn Texture>,
+    specular: Box<dyn Texture>, // 0.5 で屈折率 1.5 相当
+    specular_tint: Box<dyn Texture>,
+    sheen: Box<dyn Texture>,
+    clearcoat: Box<dyn Texture>,
+    transmission: Box<dyn Texture>,
+    anisotropic: Box<dyn Texture>,
+}
+
+impl Principled {
+    pub fn new(base_color: Box<dyn Texture>) -> Self {
+        let constant = |x: f64| -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::full(x))) };
+        Self {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            transmission: constant(0.0),
+            anisotropic: constant(0.0),
+        }
+    }
+
+    pub fn metallic(mut self, metallic: Box<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn roughness(mut self, roughness: Box<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn specular(mut self, specular: Box<dyn Texture>) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn specular_tint(mut self, specular_tint: Box<dyn Texture>) -> Self {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    pub fn sheen(mut self, sheen: Box<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn clearcoat(mut self, clearcoat: Box<dyn Texture>) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn transmission(mut self, transmission: Box<dyn Texture>) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn anisotropic(mut self, anisotropic: Box<dyn Texture>) -> Self {
+        self.anisotropic = anisotropic;
+        self
+    }
+
+    fn params(&self, hit: &HitInfo) -> Params {
+        let scalar = |t: &dyn Texture| t.value(hit.u, hit.v, hit.p).x().clamp(0.0, 1.0);
+        Params {
+            base_color: self.base_color.value(hit.u, hit.v, hit.p).saturate(),
+            metallic: scalar(self.metallic.as_ref()),
+            roughness: scalar(self.roughness.as_ref()).max(MIN_ROUGHNESS),
+            specular: scalar(self.specular.as_ref()),
+            specular_tint: scalar(self.specular_tint.as_ref()),
+            sheen: scalar(self.sheen.as_ref()),
+            clearcoat: scalar(self.clearcoat.as_ref()),
+            transmission: scalar(self.transmission.as_ref()),
+            anisotropic: scalar(self.anisotropic.as_ref()),
+        }
+    }
+}
+
+// 衝突位置でのパラメーター
+struct Params {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    anisotropic: f64,
+}
+
+impl Params {
+    fn ggx(&self) -> Ggx {
+        Ggx::anisotropic(self.roughness, self.anisotropic)
+    }
+
+    // 誘電体の垂直入射での反射率 (specular = 0.5 で 0.04)
+    fn dielectric_f0(&self) -> f64 {
+        0.08 * self.specular
+    }
+
+    // 垂直入射での鏡面反射の色 (金属は base_color、誘電体は specular_tint に応じて色づく)
+    fn f0(&self) -> Color {
+        let luminance = self.base_color.luminance();
+        let tint = if luminance > 0.0 {
+            self.base_color / luminance
+        } else {
+            Color::one()
+        };
+        let dielectric = Color::one().lerp(tint, self.specular_tint) * self.dielectric_f0();
+        dielectric.lerp(self.base_color, self.metallic)
+    }
+
+    // 透過側の屈折率の比 (屈折率は specular から決める)
+    fn eta(&self, entering: bool) -> f64 {
+        let r = self.dielectric_f0().sqrt().min(0.99);
+        let ior = (1.0 + r) / (1.0 - r);
+        if entering {
+            ior
+        } else {
+            ior.recip()
+        }
+    }
+
+    // 拡散と透過の割合
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    // wo から wi に散乱する強さ (BSDF × |cosθi|、wo.z > 0 に揃えた局所座標)
+    fn eval(&self, wi: Vec3, wo: Vec3, eta: f64) -> Color {
+        if wo.z() <= 0.0 {
+            return Color::zero();
+        }
+        let ggx = self.ggx();
+        if wi.z() <= 0.0 {
+            // 透過 (反射は鏡面反射の成分が受け持つ)
+            return match dielectric_half_vector(wo, wi, eta) {
+                Some((h, false)) => {
+                    let (doh, dih) = (wo.dot(h), wi.dot(h));
+                    let f = fresnel_dielectric(doh, eta);
+                    let denom = doh + eta * dih;
+                    // 入るときと出るときの2回で base_color になるようにする
+                    self.base_color.sqrt()
+                        * (self.transmission_weight()
+                            * (1.0 - f)
+                            * ggx.d(h)
+                            * ggx.g(wo, wi)
+                            * (doh * dih).abs()
+                            / (wo.z() * denom * denom))
+                }
+                _ => Color::zero(),
+            };
+        }
+
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(h);
+        // Burley の拡散 (粗いほど縁が明るくなる)
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = |cos: f64| 1.0 + (fd90 - 1.0) * (1.0 - cos).powi(5);
+        let diffuse = self.base_color * (FRAC_1_PI * fd(wo.z()) * fd(wi.z()));
+        let sheen = Color::full(self.sheen * (1.0 - cos_d).powi(5));
+        let specular = schlick(self.f0(), cos_d) * (ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z()));
+        let coat = Ggx::new(CLEARCOAT_ROUGHNESS);
+        let clearcoat = 0.25
+            * self.clearcoat
+            * schlick(Color::full(0.04), cos_d).x()
+            * coat.d(h)
+            * coat.g(wo, wi)
+            / (4.0 * wo.z());
+        (diffuse + sheen) * (self.diffuse_weight() * wi.z()) + specular + Color::full(clearcoat)
+    }
+
+    // 成分を選ぶ確率で混ぜた確率密度
+    fn pdf(&self, wi: Vec3, wo: Vec3, eta: f64) -> f64 {
+        let [diffuse, specular, clearcoat, transmission] = self.lobe_weights(wo);
+        let ggx = self.ggx();
+        diffuse * wi.z().max(0.0) * FRAC_1_PI
+            + specular * ggx.reflection_pdf(wo, wi)
+            + clearcoat * Ggx::new(CLEARCOAT_ROUGHNESS).reflection_pdf(wo, wi)
+            + transmission * ggx.dielectric_pdf(wo, wi, eta)
+    }
+
+    // 拡散・鏡面反射・クリアコート・透過を選ぶ確率 (見る向きでの強さに比例させる)
+    fn lobe_weights(&self, wo: Vec3) -> [f64; 4] {
+        let weights = [
+            self.diffuse_weight() * (self.base_color.luminance() + self.sheen),
+            schlick(self.f0(), wo.z()).luminance(),
+            0.25 * self.clearcoat * schlick(Color::full(0.04), wo.z()).x(),
+            self.transmission_weight() * self.base_color.sqrt().luminance(),
+        ];
+        let sum = weights.iter().sum::<f64>();
+        if sum > 0.0 {
+            weights.map(|w| w / sum)
+        } else {
+            [1.0, 0.0, 0.0, 0.0]
+        }
+    }
+}
+
+// Schlick のフレネル反射率の近似
+fn schlick(f0: Color, cos: f64) -> Color {
+    f0.lerp(Color::one(), (1.0 - cos.clamp(0.0, 1.0)).powi(5))
+}
+
+impl Material for Principled {
+    fn lobes(&self, _hit: &HitInfo) -> Lobe {
+        Lobe::DIFFUSE | Lobe::GLOSSY | Lobe::REFLECTION | Lobe::TRANSMISSION
+    }
+
+    fn eval(&self, hit: &HitInfo, wi: Vec3, wo: Vec3) -> Color {
+        let params = self.params(hit);
+        let flip = wo.z() < 0.0;
+        params.eval(flip_z(wi, flip), flip_z(wo, flip), params.eta(!flip))
+    }
+
+    fn sample(&self, hit: &HitInfo, wo: Vec3, [u0, u1, u2]: [f64; 3]) -> Option<BsdfSample> {
+        let params = self.params(hit);
+        let flip = wo.z() < 0.0;
+        let (wo, eta) = (flip_z(wo, flip), params.eta(!flip));
+        // u0 で成分を選び、選んだ成分の中での位置を u0 として使い直す
+        let (mut lobe, mut u0) = (0, u0);
+        for (i, &w) in params.lobe_weights(wo).iter().enumerate() {
+            if w > 0.0 {
+                lobe = i;
+                if u0 < w {
+                    u0 /= w;
+                    break;
+                }
+                u0 -= w;
+            }
+        }
+        let ggx = params.ggx();
+        let wi = match lobe {
+            0 => Vec3::cosine_direction([u1, u2]),
+            1 => ggx.sample_reflection(wo, [u1, u2]),
+            2 => Ggx::new(CLEARCOAT_ROUGHNESS).sample_reflection(wo, [u1, u2]),
+            _ => ggx.sample_dielectric(wo, eta, [u0, u1, u2]),
+        };
+        let pdf = params.pdf(wi, wo, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let kind = if lobe == 0 {
+            Lobe::DIFFUSE
+        } else {
+            Lobe::GLOSSY
+        };
+        let side = if wi.z() > 0.0 {
+            Lobe::REFLECTION
+        } else {
+            Lobe::TRANSMISSION
+        };
+        Some(BsdfSample::new(
+            flip_z(wi, flip),
+            params.eval(wi, wo, eta) / pdf,
+            pdf,
+            kind | side,
+        ))
+    }
+
+    fn pdf(&self, hit: &HitInfo, wi: Vec3, wo: Vec3) -> f64 {
+        let params = self.params(hit);
+        let flip = wo.z() < 0.0;
+        params.pdf(flip_z(wi, flip), flip_z(wo, flip), params.eta(!flip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 白い材質は光を増やさず、透過させると大半が面の下に抜ける
+    #[test]
+    fn test_albedo() {
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let constant = |x: f64| -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::full(x))) };
+        // (材質, 反射率の範囲, 面の下に向かう割合の範囲)
+        let cases = [
+            (
+                Principled::new(constant(1.0)).specular(constant(0.0)),
+                (0.95, 1.05),
+                (0.0, 0.0),
+            ),
+            (
+                Principled::new(constant(1.0))
+                    .metallic(constant(1.0))
+                    .roughness(constant(0.3))
+                    .anisotropic(constant(1.0)),
+                (0.85, 1.0),
+                (0.0, 0.0),
+            ),
+            // 屈折で放射輝度は 1/屈折率² になる
+            (
+                Principled::new(constant(1.0))
+                    .transmission(constant(1.0))
+                    .clearcoat(constant(1.0)),
+                (0.4, 0.55),
+                (0.7, 1.0),
+            ),
+        ];
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let hit = HitInfo::new(
+            1.0,
+            Point3::zero(),
+            Vec3::zaxis(),
+            Arc::new(Principled::new(constant(1.0))),
+            0.0,
+            0.0,
+        );
+        let n = 20000;
+        for (material, (min, max), (min_below, max_below)) in cases.iter() {
+            let mut albedo = 0.0;
+            let mut below = 0;
+            for _ in 0..n {
+                let u = [sampler.next_f64(), sampler.next_f64(), sampler.next_f64()];
+                if let Some(sample) = material.sample(&hit, wo, u) {
+                    assert!(
+                        (sample.pdf - material.pdf(&hit, sample.wi, wo)).abs() <= 1e-9 * sample.pdf
+                    );
+                    albedo += sample.weight.x();
+                    if sample.lobe.contains(Lobe::TRANSMISSION) {
+                        below += 1;
+                    }
+                }
+            }
+            let albedo = albedo / n as f64;
+            let below = below as f64 / n as f64;
+            assert!(*min < albedo && albedo < *max, "{}", albedo);
+            assert!(*min_below <= below && below <= *max_below, "{}", below);
+        }
+    }
+
+    // 異方性の向きは接線 dpdu に沿って回る
+    #[test]
+    fn test_anisotropic_tangent() {
+        let constant = |x: f64| -> Box<dyn Texture> { Box::new(ColorTexture::new(Color::full(x))) };
+        let material: Arc<dyn Material> = Arc::new(
+            Principled::new(constant(1.0))
+                .metallic(constant(1.0))
+                .roughness(constant(0.3))
+                .anisotropic(constant(1.0)),
+        );
+        let hit = |dpdu: Vec3| {
+            HitInfo::new(
+                1.0,
+                Point3::zero(),
+                Vec3::zaxis(),
+                Arc::clone(&material),
+                0.0,
+                0.0,
+            )
+            .with_tangents(dpdu, Vec3::zaxis().cross(dpdu))
+        };
+        let eval = |hit: &HitInfo, wi: Vec3, wo: Vec3| {
+            let frame = hit.frame();
+            hit.m.eval(
+                hit,
+                frame.project(wi.normalize()),
+                frame.project(wo.normalize()),
+            )
+        };
+        // z 軸まわりに 90° 回す
+        let rotate = |w: Vec3| Vec3::new(-w.y(), w.x(), w.z());
+        let (along_x, along_y) = (hit(Vec3::xaxis() * 3.0), hit(Vec3::yaxis()));
+        let wo = Vec3::new(0.5, 0.2, 1.0);
+        for wi in [Vec3::new(-0.6, 0.1, 1.0), Vec3::new(0.1, -0.7, 0.8)] {
+            let a = eval(&along_x, wi, wo);
+            let b = eval(&along_y, rotate(wi), rotate(wo));
+            assert!((a - b).near_zero(), "{:?} {:?}", a, b);
+            // 接線が変わればハイライトの形も変わる
+            assert!(!(a - eval(&along_y, wi, wo)).near_zero());
+        }
+    }
+}
@@ -1,6 +1,14 @@
+pub mod aabb;
 pub mod camera;
+pub mod distribution;
 pub mod float3;
+pub mod hdr;
+pub mod json;
 pub mod onb;
+pub mod perlin;
+pub mod preview;
 pub mod quat;
 pub mod ray;
 pub mod render;
+pub mod sampler;
+pub mod tonemap;
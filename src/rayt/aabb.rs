@@ -0,0 +1,146 @@
+use crate::rayt::float3::*;
+use crate::rayt::ray::*;
+
+// 軸平行境界ボックス
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AABB {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl AABB {
+    pub const fn new(min: Point3, max: Point3) -> Self {
+        Self { min, max }
+    }
+
+    // 何も含まない箱 (surrounding の単位元)
+    pub const fn empty() -> Self {
+        Self {
+            min: Point3::full(f64::INFINITY),
+            max: Point3::full(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(p0: Point3, p1: Point3) -> Self {
+        Self {
+            min: Point3::new(p0.x().min(p1.x()), p0.y().min(p1.y()), p0.z().min(p1.z())),
+            max: Point3::new(p0.x().max(p1.x()), p0.y().max(p1.y()), p0.z().max(p1.z())),
+        }
+    }
+
+    // 両方を囲む箱
+    pub fn surrounding(&self, other: &Self) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Point3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    // 最も長い軸 (0: x, 1: y, 2: z)
+    pub fn longest_axis(&self) -> usize {
+        let [x, y, z] = self.extent().to_array();
+        if x > y && x > z {
+            0
+        } else if y > z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // 表面積 (SAH のコスト計算に使う)
+    pub fn surface_area(&self) -> f64 {
+        if self.min.x() > self.max.x() {
+            return 0.0;
+        }
+        let [x, y, z] = self.extent().to_array();
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    // 8頂点
+    pub fn corners(&self) -> [Point3; 8] {
+        let (p0, p1) = (self.min, self.max);
+        [
+            Point3::new(p0.x(), p0.y(), p0.z()),
+            Point3::new(p1.x(), p0.y(), p0.z()),
+            Point3::new(p0.x(), p1.y(), p0.z()),
+            Point3::new(p1.x(), p1.y(), p0.z()),
+            Point3::new(p0.x(), p0.y(), p1.z()),
+            Point3::new(p1.x(), p0.y(), p1.z()),
+            Point3::new(p0.x(), p1.y(), p1.z()),
+            Point3::new(p1.x(), p1.y(), p1.z()),
+        ]
+    }
+
+    // スラブ法による交差判定
+    pub fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> bool {
+        let mut tmin = t0;
+        let mut tmax = t1;
+        let min = self.min.to_array();
+        let max = self.max.to_array();
+        let origin = ray.origin.to_array();
+        let direction = ray.direction.to_array();
+        for i in 0..3 {
+            let inv = direction[i].recip();
+            let mut ta = (min[i] - origin[i]) * inv;
+            let mut tb = (max[i] - origin[i]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut ta, &mut tb);
+            }
+            tmin = tmin.max(ta);
+            tmax = tmax.min(tb);
+            if tmax < tmin {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit() {
+        let aabb = AABB::new(Point3::full(-1.0), Point3::full(1.0));
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::zaxis());
+        assert!(aabb.hit(&ray, 0.001, f64::MAX));
+        assert!(!aabb.hit(&ray, 0.001, 3.0));
+
+        let ray = Ray::new(Point3::new(2.0, 0.0, -5.0), Vec3::zaxis());
+        assert!(!aabb.hit(&ray, 0.001, f64::MAX));
+
+        // 軸に平行なレイ(方向成分0)でも判定できる
+        let ray = Ray::new(Point3::new(0.5, 0.5, -5.0), Vec3::zaxis());
+        assert!(aabb.hit(&ray, 0.001, f64::MAX));
+    }
+
+    #[test]
+    fn test_surrounding() {
+        let a = AABB::new(Point3::zero(), Point3::one());
+        let b = AABB::new(Point3::full(-1.0), Point3::new(0.5, 2.0, 0.5));
+        let expect = AABB::new(Point3::full(-1.0), Point3::new(1.0, 2.0, 1.0));
+        assert_eq!(expect, a.surrounding(&b));
+        assert_eq!(a, AABB::empty().surrounding(&a));
+        assert_eq!(0.0, AABB::empty().surface_area());
+        assert_eq!(6.0, a.surface_area());
+    }
+}
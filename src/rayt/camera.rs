@@ -1,44 +1,435 @@
+use crate::consts::*;
 use crate::rayt::float3::*;
 use crate::rayt::ray::*;
+use crate::rayt::sampler::*;
+use std::str::FromStr;
+
+// 画面上の位置 (u, v) ∈ [0, 1]² を光線に変換する
+pub trait Camera: Send + Sync {
+    // 投影範囲の外 (魚眼の円の外側など) は None
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray>;
+}
+
+// シャッターが開いている時間 (モーションブラー)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    pub const fn new(open: f64, close: f64) -> Self {
+        Self { open, close }
+    }
+
+    // 開いている場合は sampler から1次元分を取る
+    fn time(&self, sampler: &mut dyn Sampler) -> f64 {
+        if self.close > self.open {
+            self.open + (self.close - self.open) * sampler.next_f64()
+        } else {
+            self.open
+        }
+    }
+}
+
+// 投影の方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,    // 平行投影 (ピントの合う面での透視投影と同じ範囲を写す)
+    Fisheye(f64),    // 等距離射影の魚眼 (画面の縦方向の画角、度)
+    Equirectangular, // 全天球 (横 360°、縦 180°)
+}
+
+impl Projection {
+    pub const NAMES: &'static [&'static str] = &[
+        "perspective",
+        "orthographic",
+        "fisheye[:FOV]",
+        "equirectangular",
+    ];
+
+    const DEFAULT_FISHEYE_FOV: f64 = 180.0;
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        match (name, param) {
+            ("perspective", None) => Ok(Projection::Perspective),
+            ("orthographic", None) => Ok(Projection::Orthographic),
+            ("fisheye", None) => Ok(Projection::Fisheye(Self::DEFAULT_FISHEYE_FOV)),
+            ("fisheye", Some(fov)) => match fov.parse::<f64>() {
+                Ok(fov) if fov > 0.0 && fov <= 360.0 => Ok(Projection::Fisheye(fov)),
+                _ => Err(format!("invalid field of view {:?}", fov)),
+            },
+            ("equirectangular", None) => Ok(Projection::Equirectangular),
+            _ => Err(format!(
+                "unknown projection {:?} (expected one of: {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// カメラの置き方 (投影の方式によらない設定)
+#[derive(Debug, Clone)]
+pub struct View {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,       // 縦方向の画角 (度)
+    pub aperture: f64,   // レンズの半径 (0 ならピンホールカメラ)
+    pub focus_dist: f64, // ピントの合う距離
+    pub blades: usize,   // 絞り羽根の枚数 (0 なら円形)
+    pub shutter: Shutter,
+    pub projection: Projection,
+}
+
+impl View {
+    // 注視点にピントの合うピンホールの透視投影
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, vfov: f64) -> Self {
+        Self {
+            lookfrom,
+            lookat,
+            vup,
+            vfov,
+            aperture: 0.0,
+            focus_dist: (lookat - lookfrom).length(),
+            blades: 0,
+            shutter: Shutter::default(),
+            projection: Projection::default(),
+        }
+    }
+
+    pub fn aperture(mut self, aperture: f64, focus_dist: f64) -> Self {
+        self.aperture = aperture;
+        self.focus_dist = focus_dist;
+        self
+    }
+
+    pub fn blades(mut self, blades: usize) -> Self {
+        self.blades = blades;
+        self
+    }
+
+    pub fn shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter = Shutter::new(open, close);
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    // 右・上・後ろ向きの正規直交基底
+    fn basis(&self) -> [Vec3; 3] {
+        let w = (self.lookfrom - self.lookat).normalize();
+        let u = self.vup.cross(w).normalize();
+        [u, w.cross(u), w]
+    }
+
+    pub fn camera(&self, aspect: f64) -> Box<dyn Camera> {
+        let [u, v, w] = self.basis();
+        match self.projection {
+            Projection::Perspective => Box::new(
+                PerspectiveCamera::from_lookat(
+                    self.lookfrom,
+                    self.lookat,
+                    self.vup,
+                    self.vfov,
+                    aspect,
+                    self.aperture,
+                    self.focus_dist,
+                )
+                .blades(self.blades)
+                .shutter(self.shutter),
+            ),
+            Projection::Orthographic => {
+                let halfh = self.focus_dist * (self.vfov.to_radians() * 0.5).tan();
+                let halfw = aspect * halfh;
+                Box::new(OrthographicCamera {
+                    origin: self.lookfrom - halfw * u - halfh * v,
+                    u: 2.0 * halfw * u,
+                    v: 2.0 * halfh * v,
+                    direction: -w,
+                    shutter: self.shutter,
+                })
+            }
+            Projection::Fisheye(fov) => Box::new(FisheyeCamera {
+                origin: self.lookfrom,
+                basis: [u, v, w],
+                half_fov: fov.to_radians() * 0.5,
+                aspect,
+                shutter: self.shutter,
+            }),
+            Projection::Equirectangular => Box::new(EquirectangularCamera {
+                origin: self.lookfrom,
+                basis: [u, v, w],
+                shutter: self.shutter,
+            }),
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     pub origin: Point3,
-    pub u: Vec3,
-    pub v: Vec3,
-    pub w: Vec3,
+    pub u: Vec3, // ピントの合う面での画面の横幅
+    pub v: Vec3, // ピントの合う面での画面の縦幅
+    pub w: Vec3, // ピントの合う面での画面の左下
+    lens_radius: f64,
+    blades: usize, // 絞り羽根の枚数 (3 未満なら円形の絞り)
+    shutter: Shutter,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(u: Vec3, v: Vec3, w: Vec3) -> Self {
         Self {
             origin: Point3::zero(),
             u,
             v,
             w,
+            lens_radius: 0.0,
+            blades: 0,
+            shutter: Shutter::default(),
         }
     }
 
-    pub fn from_lookat(origin: Vec3, lookat: Vec3, vup: Vec3, vfov: f64, aspect: f64) -> Self {
+    // aperture: レンズの半径 (0 ならピンホールカメラ)
+    // focus_dist: origin からピントの合う面までの距離
+    pub fn from_lookat(
+        origin: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        vfov: f64,
+        aspect: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> Self {
         let halfh = (vfov.to_radians() * 0.5).tan();
         let halfw = aspect * halfh;
         let w = (origin - lookat).normalize();
         let u = vup.cross(w).normalize();
         let v = w.cross(u);
-        let uw = halfw * u;
-        let vh = halfh * v;
+        let uw = focus_dist * halfw * u;
+        let vh = focus_dist * halfh * v;
         Self {
             origin,
             u: 2.0 * uw,
             v: 2.0 * vh,
-            w: origin - uw - vh - w,
+            w: origin - uw - vh - focus_dist * w,
+            lens_radius: aperture,
+            blades: 0,
+            shutter: Shutter::default(),
         }
     }
 
-    pub fn ray(&self, u: f64, v: f64) -> Ray {
-        Ray {
-            origin: self.origin,
-            direction: self.w + self.u * u + self.v * v - self.origin,
+    // 絞りを正多角形にする (ボケの形が多角形になる)
+    pub fn blades(mut self, blades: usize) -> Self {
+        self.blades = blades;
+        self
+    }
+
+    // シャッターが開いている間の時刻で光線を飛ばす (モーションブラー)
+    pub fn shutter(mut self, shutter: Shutter) -> Self {
+        self.shutter = shutter;
+        self
+    }
+
+    // 絞りの中の一様な点 (半径 1 の円か、それに内接する正多角形)
+    fn sample_aperture(&self, [r1, r2]: [f64; 2]) -> (f64, f64) {
+        if self.blades < 3 {
+            let r = r1.sqrt();
+            let (y, x) = (PI2 * r2).sin_cos();
+            (r * x, r * y)
+        } else {
+            // 中心と隣り合う2頂点の三角形を1つ選び、その中の一様な点を取る
+            let n = self.blades as f64;
+            let i = (r1 * n).floor().min(n - 1.0);
+            let s = (r1 * n - i).sqrt();
+            let (y0, x0) = (PI2 * i / n).sin_cos();
+            let (y1, x1) = (PI2 * (i + 1.0) / n).sin_cos();
+            let (a, b) = (s * (1.0 - r2), s * r2);
+            (a * x0 + b * x1, a * y0 + b * y1)
+        }
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    // レンズを使う場合は sampler から2次元分を取る
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let target = self.w + self.u * u + self.v * v;
+        let origin = if self.lens_radius > 0.0 {
+            let (x, y) = self.sample_aperture(sampler.next_2d());
+            self.origin
+                + self.u.normalize() * (x * self.lens_radius)
+                + self.v.normalize() * (y * self.lens_radius)
+        } else {
+            self.origin
+        };
+        let time = self.shutter.time(sampler);
+        Some(Ray::with_time(origin, target - origin, time))
+    }
+}
+
+// 平行投影
+#[derive(Debug)]
+pub struct OrthographicCamera {
+    origin: Point3, // 画面の左下
+    u: Vec3,        // 画面の横幅
+    v: Vec3,        // 画面の縦幅
+    direction: Vec3,
+    shutter: Shutter,
+}
+
+impl Camera for OrthographicCamera {
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let origin = self.origin + self.u * u + self.v * v;
+        let time = self.shutter.time(sampler);
+        Some(Ray::with_time(origin, self.direction, time))
+    }
+}
+
+// 等距離射影の魚眼 (画面中心からの距離が光軸からの角度に比例する)
+#[derive(Debug)]
+pub struct FisheyeCamera {
+    origin: Point3,
+    basis: [Vec3; 3], // 右・上・後ろ
+    half_fov: f64,    // 画面の縦半分に写る角度 (ラジアン)
+    aspect: f64,
+    shutter: Shutter,
+}
+
+impl Camera for FisheyeCamera {
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        // 画面の縦半分が 1 になる座標
+        let x = (2.0 * u - 1.0) * self.aspect;
+        let y = 2.0 * v - 1.0;
+        let r = (x * x + y * y).sqrt();
+        // 画角の円の外側は写らない
+        if r > 1.0 {
+            return None;
         }
+        let theta = r * self.half_fov;
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let [bu, bv, bw] = self.basis;
+        let direction = if r > 0.0 {
+            (x * bu + y * bv) * (sin_theta / r) - cos_theta * bw
+        } else {
+            -bw
+        };
+        let time = self.shutter.time(sampler);
+        Some(Ray::with_time(self.origin, direction, time))
+    }
+}
+
+// 全天球 (正距円筒図法、画面の中心が注視点の方向)
+#[derive(Debug)]
+pub struct EquirectangularCamera {
+    origin: Point3,
+    basis: [Vec3; 3], // 右・上・後ろ
+    shutter: Shutter,
+}
+
+impl Camera for EquirectangularCamera {
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let phi = (u - 0.5) * PI2;
+        let theta = (v - 0.5) * PI;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let [bu, bv, bw] = self.basis;
+        let direction = cos_theta * (sin_phi * bu - cos_phi * bw) + sin_theta * bv;
+        let time = self.shutter.time(sampler);
+        Some(Ray::with_time(self.origin, direction, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 同じ画素を通る光線はレンズ上の位置によらずピントの合う面で1点に集まる
+    #[test]
+    fn test_focus() {
+        let origin = Point3::new(0.0, 1.0, 5.0);
+        let lookat = Point3::new(0.0, 1.0, 0.0);
+        let camera =
+            PerspectiveCamera::from_lookat(origin, lookat, Vec3::yaxis(), 40.0, 1.5, 0.5, 3.0)
+                .blades(6);
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let focus = camera.w + camera.u * 0.3 + camera.v * 0.8;
+        assert!(((focus - origin).dot(Vec3::zaxis()) + 3.0).abs() < 1e-9);
+        for _ in 0..16 {
+            let ray = camera.ray(0.3, 0.8, sampler.as_mut()).unwrap();
+            assert!((ray.origin - origin).length() <= 0.5 + 1e-9);
+            assert!((ray.origin + ray.direction - focus).near_zero());
+        }
+    }
+
+    #[test]
+    fn test_aperture() {
+        let camera = PerspectiveCamera::new(Vec3::xaxis(), Vec3::yaxis(), Vec3::zero()).blades(5);
+        // 正五角形の内接円の半径
+        let inradius = (PI / 5.0).cos();
+        let mut outside = false;
+        for i in 0..64 {
+            let (x, y) = camera.sample_aperture([i as f64 / 64.0, (i * 37 % 64) as f64 / 64.0]);
+            assert!(x * x + y * y <= 1.0 + 1e-9);
+            outside |= x * x + y * y > inradius * inradius;
+        }
+        assert!(outside);
+    }
+
+    #[test]
+    fn test_projection() {
+        let view = View::new(Point3::zero(), -Vec3::zaxis(), Vec3::yaxis(), 90.0);
+        let mut sampler = SamplerKind::Independent.create(0, 0, 0, 1);
+        let mut direction = |projection, u, v| {
+            view.clone()
+                .projection(projection)
+                .camera(1.0)
+                .ray(u, v, sampler.as_mut())
+                .map(|ray| ray.direction.normalize())
+        };
+        let near = |a: Option<Vec3>, b: Vec3| (a.unwrap() - b).near_zero();
+        let forward = -Vec3::zaxis();
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Fisheye(180.0),
+            Projection::Equirectangular,
+        ] {
+            assert!(near(direction(projection, 0.5, 0.5), forward));
+        }
+        // 平行投影はどこでも同じ向き
+        assert!(near(direction(Projection::Orthographic, 0.1, 0.9), forward));
+        // 180° の魚眼の縦の端は真上、画角の外は写らない
+        assert!(near(
+            direction(Projection::Fisheye(180.0), 0.5, 1.0),
+            Vec3::yaxis()
+        ));
+        assert!(direction(Projection::Fisheye(180.0), 0.0, 0.0).is_none());
+        // 全天球の左右の端は真後ろ
+        assert!(near(
+            direction(Projection::Equirectangular, 0.0, 0.5),
+            Vec3::zaxis()
+        ));
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Ok(Projection::Fisheye(180.0)), "fisheye".parse());
+        assert_eq!(Ok(Projection::Fisheye(220.0)), "fisheye:220".parse());
+        assert!("fisheye:0".parse::<Projection>().is_err());
+        assert!("orthographic:1".parse::<Projection>().is_err());
     }
 }
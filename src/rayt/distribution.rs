@@ -0,0 +1,145 @@
+// 区分定数の確率分布 (値に比例した確率でサンプリングする)
+
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>, // func.len() + 1 個 (先頭は 0、末尾は 1)
+    integral: f64, // [0, 1] での func の積分
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for (i, f) in func.iter().enumerate() {
+            cdf.push(cdf[i] + f.abs() / n as f64);
+        }
+        let integral = cdf[n];
+        if integral > 0.0 {
+            cdf.iter_mut().skip(1).for_each(|c| *c /= integral);
+        } else {
+            // 全て 0 なら一様分布にする
+            cdf.iter_mut()
+                .enumerate()
+                .for_each(|(i, c)| *c = i as f64 / n as f64);
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // u ∈ [0, 1) から (x ∈ [0, 1), pdf, 区間の番号) を返す
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // cdf[i] <= u となる最後の i
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        ((i as f64 + du) / self.len() as f64, self.pdf_at(i), i)
+    }
+
+    fn pdf_at(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    // x ∈ [0, 1] での確率密度
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.len() as f64) as usize).min(self.len() - 1);
+        self.pdf_at(i)
+    }
+}
+
+// 行ごとの条件付き分布と、行を選ぶ周辺分布からなる2次元の分布
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // func は width * height 個で、行順 (v が行)
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    // [u, v] ∈ [0, 1)² から (点 [u, v], pdf) を返す
+    pub fn sample(&self, [u0, u1]: [f64; 2]) -> ([f64; 2], f64) {
+        let (v, pdf1, row) = self.marginal.sample(u1);
+        let (u, pdf0, _) = self.conditional[row].sample(u0);
+        ([u, v], pdf0 * pdf1)
+    }
+
+    pub fn pdf(&self, [u, v]: [f64; 2]) -> f64 {
+        let height = self.conditional.len();
+        let row = ((v * height as f64) as usize).min(height - 1);
+        self.conditional[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution1d() {
+        let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert_eq!(2.0, d.integral());
+        // 2番目の区間 (確率 3/8) の中の点
+        let (x, pdf, i) = d.sample(0.125 + 0.1875);
+        assert_eq!(1, i);
+        assert!((x - 0.375).abs() < 1e-12);
+        assert_eq!(1.5, pdf);
+        assert_eq!(pdf, d.pdf(x));
+        // 値が 0 の区間は選ばれない
+        assert_eq!(3, d.sample(0.5).2);
+        assert_eq!(0.0, d.pdf(0.6));
+    }
+
+    #[test]
+    fn test_distribution2d() {
+        let func = [0.0, 1.0, 3.0, 0.0, 0.0, 2.0, 0.0, 2.0];
+        let d = Distribution2D::new(&func, 4, 2);
+        let n = 64;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = [(i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64];
+                let (p, pdf) = d.sample(u);
+                assert!((pdf - d.pdf(p)).abs() < 1e-12);
+                assert!(pdf > 0.0);
+                sum += 1.0 / pdf;
+            }
+        }
+        // 値が正の領域の面積 (1/2) の推定
+        assert!((sum / (n * n) as f64 - 0.5).abs() < 1e-9);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::consts::*;
-use rand::prelude::*;
+use crate::rayt::sampler::*;
 use std::iter::FromIterator;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -186,41 +186,86 @@ impl Float3 {
     pub fn degamma(&self, factor: f64) -> Self {
         Self::from_iter(self.0.iter().map(|x| x.powf(factor)))
     }
+
+    // 輝度 (Rec. 709 の係数)
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.0[0] + 0.7152 * self.0[1] + 0.0722 * self.0[2]
+    }
+
+    // リニア空間からsRGB空間へ (IEC 61966-2-1 の区分的な変換)
+    pub fn linear_to_srgb(&self) -> Self {
+        Self::from_iter(self.0.iter().map(|&x| {
+            if x <= 0.003_130_8 {
+                12.92 * x
+            } else {
+                1.055 * x.powf(2.4f64.recip()) - 0.055
+            }
+        }))
+    }
+
+    // sRGB空間からリニア空間へ (linear_to_srgb の逆変換)
+    pub fn srgb_to_linear(&self) -> Self {
+        Self::from_iter(self.0.iter().map(|&x| {
+            if x <= 0.040_45 {
+                x / 12.92
+            } else {
+                ((x + 0.055) / 1.055).powf(2.4)
+            }
+        }))
+    }
 }
 
 impl Float3 {
-    pub fn random() -> Self {
-        Self::new(random::<f64>(), random::<f64>(), random::<f64>())
+    pub fn random(sampler: &mut dyn Sampler) -> Self {
+        Self::new(sampler.next_f64(), sampler.next_f64(), sampler.next_f64())
     }
 
-    pub fn random_full() -> Self {
-        Self::full(random::<f64>())
+    pub fn random_full(sampler: &mut dyn Sampler) -> Self {
+        Self::full(sampler.next_f64())
     }
 
-    pub fn random_limit(min: f64, max: f64) -> Self {
-        Self::from_iter(Self::random().0.iter().map(|x| min + x * (max - min)))
+    pub fn random_limit(min: f64, max: f64, sampler: &mut dyn Sampler) -> Self {
+        Self::from_iter(
+            Self::random(sampler)
+                .0
+                .iter()
+                .map(|x| min + x * (max - min)),
+        )
     }
 
     // 単位球の中の任意の点を生成
-    pub fn random_in_unit_sphere() -> Self {
+    pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Self {
         loop {
-            let point = Self::random_limit(-1.0, 1.0);
+            let point = Self::random_limit(-1.0, 1.0, sampler);
             if point.length_squared() < 1.0 {
                 return point;
             }
         }
     }
 
-    pub fn random_cosine_direction() -> Self {
-        let Self([r1, r2, _]) = Self::random();
+    // [0, 1)³ の乱数から、単位球の中の一様な点を作る
+    pub fn in_unit_sphere([r1, r2, r3]: [f64; 3]) -> Self {
+        let r = r1.cbrt();
+        let z = 1.0 - 2.0 * r2;
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (PI2 * r3).sin_cos();
+        Self::new(sin_theta * cos_phi, sin_theta * sin_phi, z) * r
+    }
+
+    pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Self {
+        Self::cosine_direction(sampler.next_2d())
+    }
+
+    // [0, 1)² の乱数から、z 軸まわりの cos に比例した向きを作る
+    pub fn cosine_direction([r1, r2]: [f64; 2]) -> Self {
         let z = (1.0 - r2).sqrt();
         let (x, y) = (PI2 * r1).sin_cos();
         let r2sqrt = r2.sqrt();
         Self::new(x * r2sqrt, y * r2sqrt, z)
     }
 
-    pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Self {
-        let Self([rx, ry, _]) = Self::random();
+    pub fn random_to_sphere(radius: f64, distance_squared: f64, sampler: &mut dyn Sampler) -> Self {
+        let [rx, ry] = sampler.next_2d();
         let rr = radius.powi(2).min(distance_squared);
         let cos_theta_max = (1.0 - rr * distance_squared.recip()).sqrt();
         let z = 1.0 - ry * (1.0 - cos_theta_max);
@@ -386,4 +431,17 @@ mod tests {
 
         // TODO assign 系のテスト
     }
+
+    #[test]
+    fn test_srgb() {
+        let linear = Float3::new(0.0, 0.002, 0.5);
+        let srgb = linear.linear_to_srgb();
+        assert_eq!(0.0, srgb.x());
+        assert!((srgb.y() - 0.02584).abs() < 1e-5);
+        assert!((srgb.z() - 0.73536).abs() < 1e-5);
+        assert!((Float3::one().linear_to_srgb() - Float3::one()).near_zero());
+
+        let back = srgb.srgb_to_linear();
+        assert!((back - linear).near_zero());
+    }
 }
@@ -0,0 +1,193 @@
+use crate::rayt::float3::*;
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::{ImageError, ImageResult, Rgb};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// クランプしていないリニアな放射輝度をそのまま読み書きする
+//   .hdr: Radiance RGBE
+//   .pfm: Portable Float Map
+
+pub fn is_hdr_path(path: impl AsRef<Path>) -> bool {
+    matches!(
+        extension(path.as_ref()).as_deref(),
+        Some("hdr") | Some("pfm")
+    )
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+// pixels は左上から行順
+pub fn save_hdr(
+    pixels: &[Color],
+    width: u32,
+    height: u32,
+    path: impl AsRef<Path>,
+) -> ImageResult<()> {
+    let path = path.as_ref();
+    let file = BufWriter::new(File::create(path)?);
+    match extension(path).as_deref() {
+        Some("hdr") => {
+            let data = pixels
+                .iter()
+                .map(|c| {
+                    let [r, g, b] = c.to_array();
+                    Rgb([r as f32, g as f32, b as f32])
+                })
+                .collect::<Vec<_>>();
+            HdrEncoder::new(file).encode(&data, width as usize, height as usize)
+        }
+        Some("pfm") => write_pfm(file, pixels, width, height).map_err(ImageError::IoError),
+        _ => Err(ImageError::IoError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{:?} is not a .hdr or .pfm file", path),
+        ))),
+    }
+}
+
+// PFM は下の行から格納する。スケールが負ならリトルエンディアン
+pub fn write_pfm(
+    mut w: impl Write,
+    pixels: &[Color],
+    width: u32,
+    height: u32,
+) -> std::io::Result<()> {
+    write!(w, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in pixels.chunks(width as usize).rev() {
+        for c in row {
+            for x in c.iter() {
+                w.write_all(&(*x as f32).to_le_bytes())?;
+            }
+        }
+    }
+    w.flush()
+}
+
+// リニアな色として画像を読み込む (HDR 以外は sRGB からリニアに戻す)
+// 戻り値は (幅, 高さ, 左上から行順の画素)
+pub fn load_image(path: impl AsRef<Path>) -> ImageResult<(u32, u32, Vec<Color>)> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("hdr") => {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .iter()
+                .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                .collect();
+            Ok((meta.width, meta.height, pixels))
+        }
+        Some("pfm") => read_pfm(BufReader::new(File::open(path)?)).map_err(ImageError::IoError),
+        _ => {
+            let img = image::open(path)?.to_rgb8();
+            let (width, height) = img.dimensions();
+            let pixels = img
+                .pixels()
+                .map(|p| Color::from_rgb(p[0], p[1], p[2]).srgb_to_linear())
+                .collect();
+            Ok((width, height, pixels))
+        }
+    }
+}
+
+// "PF" はカラー、"Pf" はグレースケール
+pub fn read_pfm(mut r: impl BufRead) -> std::io::Result<(u32, u32, Vec<Color>)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    // ヘッダーは空白区切りの4語
+    let mut words = Vec::new();
+    while words.len() < 4 {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Err(invalid("truncated PFM header"));
+        }
+        words.extend(line.split_whitespace().map(String::from));
+    }
+    let channels = match words[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let parse = |s: &str| s.parse::<f64>().map_err(|_| invalid("invalid PFM header"));
+    let width = parse(&words[1])? as usize;
+    let height = parse(&words[2])? as usize;
+    let little_endian = parse(&words[3])? < 0.0;
+
+    let mut data = vec![0u8; width * height * channels * 4];
+    r.read_exact(&mut data)?;
+    let values = data
+        .chunks(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b) as f64
+            } else {
+                f32::from_be_bytes(b) as f64
+            }
+        })
+        .collect::<Vec<_>>();
+    let pixels = values
+        .chunks(width * channels)
+        .rev()
+        .flat_map(|row| row.chunks(channels))
+        .map(|c| match c {
+            [r, g, b] => Color::new(*r, *g, *b),
+            _ => Color::full(c[0]),
+        })
+        .collect();
+    Ok((width as u32, height as u32, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_pfm() {
+        let pixels = [
+            Color::new(15.0, 0.0, 0.0),
+            Color::zero(),
+            Color::zero(),
+            Color::new(0.0, 0.0, 0.5),
+        ];
+        let mut buf = Vec::new();
+        write_pfm(&mut buf, &pixels, 2, 2).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&buf[..header.len()], header);
+        let data = &buf[header.len()..];
+        assert_eq!(4 * 3 * 4, data.len());
+        // 最初に書かれるのは最下行
+        assert_eq!(0.5f32.to_le_bytes(), data[20..24]);
+        // 1.0 を超える値もそのまま残る
+        assert_eq!(15.0f32.to_le_bytes(), data[24..28]);
+    }
+
+    #[test]
+    fn test_read_pfm() {
+        let pixels = [
+            Color::new(15.0, 0.0, 0.0),
+            Color::zero(),
+            Color::zero(),
+            Color::new(0.0, 0.0, 0.5),
+        ];
+        let mut buf = Vec::new();
+        write_pfm(&mut buf, &pixels, 2, 2).unwrap();
+        let (width, height, read) = read_pfm(&buf[..]).unwrap();
+        assert_eq!((2, 2), (width, height));
+        assert_eq!(&pixels[..], &read[..]);
+        assert!(read_pfm(&b"P6\n2 2\n255\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_is_hdr_path() {
+        assert!(is_hdr_path("render.hdr"));
+        assert!(is_hdr_path("out/Render.PFM"));
+        assert!(!is_hdr_path("render.png"));
+        assert!(!is_hdr_path("render"));
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+
+// 行番号つきの JSON 値 (シーンファイルのエラー報告用)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json {
+    pub line: usize,
+    pub value: JsonValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>), // 記述順を保持する
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl JsonError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl Json {
+    pub fn parse(src: &str) -> Result<Self, JsonError> {
+        let mut parser = Parser {
+            chars: src.chars().collect(),
+            pos: 0,
+            line: 1,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("trailing characters after JSON value"));
+        }
+        Ok(value)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.value {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "bool",
+            JsonValue::Number(_) => "number",
+            JsonValue::String(_) => "string",
+            JsonValue::Array(_) => "array",
+            JsonValue::Object(_) => "object",
+        }
+    }
+
+    fn expected(&self, what: &str) -> JsonError {
+        JsonError::new(
+            self.line,
+            format!("expected {}, found {}", what, self.type_name()),
+        )
+    }
+
+    pub fn as_f64(&self) -> Result<f64, JsonError> {
+        match self.value {
+            JsonValue::Number(x) => Ok(x),
+            _ => Err(self.expected("number")),
+        }
+    }
+
+    pub fn as_usize(&self) -> Result<usize, JsonError> {
+        let x = self.as_f64()?;
+        if x >= 0.0 && x.fract() == 0.0 {
+            Ok(x as usize)
+        } else {
+            Err(JsonError::new(
+                self.line,
+                format!("expected non-negative integer, found {}", x),
+            ))
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, JsonError> {
+        match self.value {
+            JsonValue::Bool(b) => Ok(b),
+            _ => Err(self.expected("bool")),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, JsonError> {
+        match &self.value {
+            JsonValue::String(s) => Ok(s),
+            _ => Err(self.expected("string")),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Json], JsonError> {
+        match &self.value {
+            JsonValue::Array(a) => Ok(a),
+            _ => Err(self.expected("array")),
+        }
+    }
+
+    pub fn as_object(&self) -> Result<&[(String, Json)], JsonError> {
+        match &self.value {
+            JsonValue::Object(o) => Ok(o),
+            _ => Err(self.expected("object")),
+        }
+    }
+
+    // オブジェクトのキーを探す (オブジェクト以外は None)
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match &self.value {
+            JsonValue::Object(o) => o.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // 必須のキー
+    pub fn field(&self, key: &str) -> Result<&Json, JsonError> {
+        self.as_object()?;
+        self.get(key)
+            .ok_or_else(|| JsonError::new(self.line, format!("missing field {:?}", key)))
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    depth: usize, // 入れ子になっている配列・オブジェクトの数
+}
+
+impl Parser {
+    // 深すぎる入れ子でスタックがあふれないようにする
+    const MAX_DEPTH: usize = 128;
+
+    fn error(&self, message: impl Into<String>) -> JsonError {
+        JsonError::new(self.line, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("expected '{}', found end of input", expected))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        let line = self.line;
+        let value = match self.peek() {
+            Some('{') | Some('[') if self.depth >= Self::MAX_DEPTH => {
+                return Err(self.error("nesting too deep"))
+            }
+            Some('{') => self.nested(Self::object)?,
+            Some('[') => self.nested(Self::array)?,
+            Some('"') => JsonValue::String(self.string()?),
+            Some('t') => self.keyword("true", JsonValue::Bool(true))?,
+            Some('f') => self.keyword("false", JsonValue::Bool(false))?,
+            Some('n') => self.keyword("null", JsonValue::Null)?,
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number()?,
+            Some(c) => return Err(self.error(format!("unexpected character '{}'", c))),
+            None => return Err(self.error("unexpected end of input")),
+        };
+        Ok(Json { line, value })
+    }
+
+    fn nested(
+        &mut self,
+        f: fn(&mut Self) -> Result<JsonValue, JsonError>,
+    ) -> Result<JsonValue, JsonError> {
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn keyword(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        for expected in word.chars() {
+            if self.bump() != Some(expected) {
+                return Err(self.error(format!("invalid literal, expected {:?}", word)));
+            }
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| self.error(format!("invalid number {:?}", text)))
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                            .ok_or_else(|| self.error(format!("invalid escape \\u{}", hex)))?;
+                        s.push(c);
+                    }
+                    Some(c) => return Err(self.error(format!("invalid escape '\\{}'", c))),
+                    None => return Err(self.error("unterminated string")),
+                },
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.bump();
+            return Ok(JsonValue::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => return Ok(JsonValue::Array(values)),
+                _ => return Err(self.error("expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('{')?;
+        let mut members: Vec<(String, Json)> = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            if members.iter().any(|(k, _)| *k == key) {
+                return Err(self.error(format!("duplicate key {:?}", key)));
+            }
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+            members.push((key, value));
+            self.skip_whitespace();
+            match self.bump() {
+                Some(',') => {}
+                Some('}') => return Ok(JsonValue::Object(members)),
+                _ => return Err(self.error("expected ',' or '}' in object")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = Json::parse(
+            r#"{
+                "a": [1, -2.5e1, true, null],
+                "b": {"c": "d\nA"}
+            }"#,
+        )
+        .unwrap();
+        let a = json.field("a").unwrap();
+        assert_eq!(2, a.line);
+        let a = a.as_array().unwrap();
+        assert_eq!(1.0, a[0].as_f64().unwrap());
+        assert_eq!(-25.0, a[1].as_f64().unwrap());
+        assert!(a[2].as_bool().unwrap());
+        assert_eq!(JsonValue::Null, a[3].value);
+
+        let c = json.field("b").unwrap().field("c").unwrap();
+        assert_eq!("d\nA", c.as_str().unwrap());
+        assert_eq!(3, c.line);
+    }
+
+    #[test]
+    fn test_error_line() {
+        let err = Json::parse("{\n  \"a\": 1,\n  \"b\": [1 2]\n}").unwrap_err();
+        assert_eq!(3, err.line);
+
+        let json = Json::parse("{\n\"a\":\n\"x\"}").unwrap();
+        let err = json.field("a").unwrap().as_f64().unwrap_err();
+        assert_eq!(3, err.line);
+        assert_eq!("line 3: expected number, found string", err.to_string());
+
+        let err = json.field("z").unwrap_err();
+        assert_eq!(1, err.line);
+    }
+
+    #[test]
+    fn test_nesting() {
+        let nested = |n: usize| format!("{}1{}", "[".repeat(n), "]".repeat(n));
+        assert!(Json::parse(&nested(Parser::MAX_DEPTH)).is_ok());
+        let src = format!("{{\n\"a\": {}}}", nested(100_000));
+        let err = Json::parse(&src).unwrap_err();
+        assert_eq!("line 2: nesting too deep", err.to_string());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::rayt::float3::*;
 
 // 正規直交基底
+#[derive(Clone, Copy)]
 pub struct ONB {
     axis: [Vec3; 3],
 }
@@ -17,6 +18,20 @@ impl ONB {
         Self { axis: [u, v, w] }
     }
 
+    // w を n、u を t の接平面への射影の向きにとる (t が n と平行なら new と同じ)
+    pub fn with_tangent(n: Vec3, t: Vec3) -> Self {
+        let w = n.normalize();
+        let u = t - w * t.dot(w);
+        if u.length_squared() > 1e-12 * t.length_squared() {
+            let u = u.normalize();
+            Self {
+                axis: [u, w.cross(u), w],
+            }
+        } else {
+            Self::new(n)
+        }
+    }
+
     pub fn u(&self) -> Vec3 {
         self.axis[0]
     }
@@ -30,4 +45,9 @@ impl ONB {
     pub fn local(&self, v: Vec3) -> Vec3 {
         self.axis[0] * v.x() + self.axis[1] * v.y() + self.axis[2] * v.z()
     }
+
+    // local の逆 (ワールド座標の v を基底の成分に分ける)
+    pub fn project(&self, v: Vec3) -> Vec3 {
+        Vec3::new(self.u().dot(v), self.v().dot(v), self.w().dot(v))
+    }
 }
@@ -0,0 +1,153 @@
+use crate::rayt::float3::*;
+use crate::rayt::sampler::SplitMix;
+
+const PERM_SIZE: usize = 256;
+
+// 勾配ノイズ (Improved Perlin Noise)
+// 置換テーブルはシードから生成するので同じシードなら同じ模様になる
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    perm: Vec<u8>, // 0..256 の順列を2回繰り返したもの
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        // 依存クレートの更新で模様が変わらないように、自前の乱数列でシャッフルする
+        let mut rng = SplitMix::new(seed);
+        let mut perm: Vec<u8> = (0..PERM_SIZE).map(|i| i as u8).collect();
+        for i in (1..PERM_SIZE).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            perm.swap(i, j);
+        }
+        perm.extend_from_within(..);
+        Self { perm }
+    }
+
+    fn hash(&self, x: usize, y: usize, z: usize) -> u8 {
+        let p = &self.perm;
+        p[p[p[x] as usize + y] as usize + z]
+    }
+
+    // 格子点の勾配 (立方体の辺の中点方向の12本) と位置ベクトルの内積
+    fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+        let h = hash & 15;
+        let u = if h < 8 { x } else { y };
+        let v = if h < 4 {
+            y
+        } else if h == 12 || h == 14 {
+            x
+        } else {
+            z
+        };
+        (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+    }
+
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(t: f64, a: f64, b: f64) -> f64 {
+        a + t * (b - a)
+    }
+
+    // [-1, 1] のノイズ (格子点では 0)
+    pub fn noise(&self, p: Point3) -> f64 {
+        let floor = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let [x, y, z] = [p.x() - floor[0], p.y() - floor[1], p.z() - floor[2]];
+        let [i, j, k] = floor.map(|f| (f as i64).rem_euclid(PERM_SIZE as i64) as usize);
+        let (u, v, w) = (Self::fade(x), Self::fade(y), Self::fade(z));
+
+        let g = |di: usize, dj: usize, dk: usize| {
+            let hash = self.hash(i + di, j + dj, k + dk);
+            Self::grad(hash, x - di as f64, y - dj as f64, z - dk as f64)
+        };
+        Self::lerp(
+            w,
+            Self::lerp(
+                v,
+                Self::lerp(u, g(0, 0, 0), g(1, 0, 0)),
+                Self::lerp(u, g(0, 1, 0), g(1, 1, 0)),
+            ),
+            Self::lerp(
+                v,
+                Self::lerp(u, g(0, 0, 1), g(1, 0, 1)),
+                Self::lerp(u, g(0, 1, 1), g(1, 1, 1)),
+            ),
+        )
+    }
+
+    // オクターブを重ねたノイズ (fBm)、おおよそ [-1, 1]
+    pub fn fbm(&self, p: Point3, octaves: usize) -> f64 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut weight = 1.0;
+        let mut p = p;
+        for _ in 0..octaves {
+            sum += weight * self.noise(p);
+            norm += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        if norm > 0.0 {
+            sum / norm
+        } else {
+            0.0
+        }
+    }
+
+    // 絶対値を重ねた乱流、[0, 1]
+    pub fn turbulence(&self, p: Point3, octaves: usize) -> f64 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut weight = 1.0;
+        let mut p = p;
+        for _ in 0..octaves {
+            sum += weight * self.noise(p).abs();
+            norm += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        if norm > 0.0 {
+            sum / norm
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let p = Point3::new(1.3, -2.7, 0.45);
+        assert_eq!(Perlin::new(7).noise(p), Perlin::new(7).noise(p));
+        assert_ne!(Perlin::new(7).noise(p), Perlin::new(8).noise(p));
+    }
+
+    // 同じシードの置換テーブルは依存クレートによらず変わらない
+    #[test]
+    fn test_permutation() {
+        let perm = Perlin::new(0).perm;
+        assert_eq!([99, 179, 124, 78, 196, 203, 221, 113], perm[..8]);
+        let mut sorted = perm[..PERM_SIZE].to_vec();
+        sorted.sort_unstable();
+        assert!(sorted.iter().enumerate().all(|(i, x)| i == *x as usize));
+        assert_eq!(perm[..PERM_SIZE], perm[PERM_SIZE..]);
+    }
+
+    #[test]
+    fn test_range() {
+        let perlin = Perlin::new(0);
+        assert_eq!(0.0, perlin.noise(Point3::new(3.0, -5.0, 12.0)));
+        for i in 0..1000 {
+            let t = i as f64 * 0.137;
+            let p = Point3::new(t, t * 0.71 - 40.0, t * 1.9);
+            let n = perlin.noise(p);
+            assert!((-1.0..=1.0).contains(&n));
+            assert!((-1.0..=1.0).contains(&perlin.fbm(p, 5)));
+            assert!((0.0..=1.0).contains(&perlin.turbulence(p, 5)));
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::rayt::float3::*;
+use crate::rayt::render::*;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use rayon::prelude::*;
+use std::time::Duration;
+
+const WINDOW_TITLE: &str = "rayt_rust";
+
+// ウィンドウを開いて1パスごとにサンプルを蓄積しながら表示する
+//   S: 現在の画像を保存
+//   Esc / Q: 終了
+pub fn preview_with_config<S>(scene: &S, config: &RenderConfig)
+where
+    S: SceneWithDepth + Sync + ?Sized,
+{
+    let ctx = RenderContext::new(scene, config);
+    let (width, height) = (ctx.width as usize, ctx.height as usize);
+
+    let mut window = Window::new(WINDOW_TITLE, width, height, WindowOptions::default())
+        .unwrap_or_else(|e| panic!("failed to open preview window: {}", e));
+    window.limit_update_rate(Some(Duration::from_millis(16)));
+
+    let mut accum = vec![Color::zero(); width * height];
+    let mut buffer = vec![0u32; width * height];
+    let mut passes = 0;
+    let mut backed_up = !config.backup;
+
+    let mut save = |accum: &[Color], passes: usize| {
+        if !backed_up {
+            // バックアップできなければ上書きせずに描画を続ける
+            if let Err(e) = backup_outputs(config) {
+                eprintln!("{}", e);
+                return;
+            }
+            backed_up = true;
+        }
+        let pixels = accum.iter().map(|c| *c / passes as f64).collect::<Vec<_>>();
+        match save_image(&pixels, ctx.width, ctx.height, config) {
+            Ok(()) => println!("saved {:?} ({} spp)", config.output, passes),
+            Err(e) => eprintln!("failed to save {:?}: {}", config.output, e),
+        }
+    };
+
+    while window.is_open() && !window.is_key_down(Key::Escape) && !window.is_key_down(Key::Q) {
+        if passes < ctx.spp {
+            accum.par_iter_mut().enumerate().for_each(|(i, color)| {
+                *color += ctx.sample((i % width) as u32, (i / width) as u32, passes);
+            });
+            passes += 1;
+
+            // 現在までの平均を表示する (0RGB)
+            let recip = (passes as f64).recip();
+            buffer
+                .par_iter_mut()
+                .zip(accum.par_iter())
+                .for_each(|(pixel, color)| {
+                    let [r, g, b] = config.to_display(*color * recip).to_rgb();
+                    *pixel = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+                });
+            window.set_title(&format!("{} - {}/{} spp", WINDOW_TITLE, passes, ctx.spp));
+
+            if passes == ctx.spp {
+                save(&accum, passes);
+            }
+        }
+
+        if passes > 0 && window.is_key_pressed(Key::S, KeyRepeat::No) {
+            save(&accum, passes);
+        }
+
+        window
+            .update_with_buffer(&buffer, width, height)
+            .unwrap_or_else(|e| panic!("failed to update preview window: {}", e));
+    }
+}
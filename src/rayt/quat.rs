@@ -61,6 +61,25 @@ impl Quat {
         Quat(self.0 * recip, self.1 * recip)
     }
 
+    // 球面線形補間 (t = 0 で self、t = 1 で rhs)
+    pub fn slerp(&self, rhs: Self, t: f64) -> Self {
+        // 短い方の弧を通るように向きをそろえる
+        let (rhs, cos) = if self.dot(rhs) < 0.0 {
+            (Quat(-rhs.0, -rhs.1), -self.dot(rhs))
+        } else {
+            (rhs, self.dot(rhs))
+        };
+        let (k0, k1) = if cos > 0.9995 {
+            // ほぼ同じ向きなら線形補間で十分
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let recip = theta.sin().recip();
+            (((1.0 - t) * theta).sin() * recip, (t * theta).sin() * recip)
+        };
+        Quat(self.0 * k0 + rhs.0 * k1, self.1 * k0 + rhs.1 * k1).normalize()
+    }
+
     pub fn to_array(&self) -> [f64; 4] {
         let [x, y, z] = self.0.to_array();
         [x, y, z, self.1]
@@ -91,9 +110,38 @@ impl std::ops::Mul<Quat> for Quat {
         let [x2, y2, z2, w2] = rhs.to_array();
         Quat::new(
             w1 * x2 + x1 * w2 + y1 * z2 - z1 * y2,
-            w1 * y2 + y2 * w2 + z1 * x2 - x1 * z2,
+            w1 * y2 + y1 * w2 + z1 * x2 - x1 * z2,
             w1 * z2 + z1 * w2 + x1 * y2 - y1 * x2,
             w1 * w2 - x1 * x2 - y1 * y2 - z1 * z2,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::*;
+
+    // 積は右から順に回転する
+    #[test]
+    fn test_mul() {
+        let q = Quat::from_rot_y(PI / 2.0) * Quat::from_rot_x(PI / 2.0);
+        assert!((q.rotate(Vec3::yaxis()) - Vec3::xaxis()).near_zero());
+        assert_eq!(Quat::from_rot_y(0.5), Quat::from_rot_y(0.5) * Quat::unit());
+    }
+
+    #[test]
+    fn test_slerp() {
+        let q0 = Quat::unit();
+        let q1 = Quat::from_rot_y(PI / 2.0);
+        assert_eq!(q0.slerp(q1, 0.0), q0);
+        let q = q0.slerp(q1, 0.5);
+        assert!(
+            (q.rotate(Vec3::xaxis()) - Quat::from_rot_y(PI / 4.0).rotate(Vec3::xaxis()))
+                .near_zero()
+        );
+        // 反対の符号でも同じ回転なので短い方の弧を通る
+        let q = q0.slerp(Quat::new(0.0, 0.0, 0.0, -1.0), 0.5);
+        assert!((q.rotate(Vec3::xaxis()) - Vec3::xaxis()).near_zero());
+    }
+}
@@ -4,11 +4,30 @@ use crate::rayt::float3::*;
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    pub time: f64, // 光線の時刻 (動く物体の位置を決める)
+    // 媒質の中で散乱するまでの距離に使う乱数の種 (積分器がサンプラーから取る)
+    pub seed: Option<u64>,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Point3, direction: Vec3, time: f64) -> Self {
+        Self {
+            origin,
+            direction,
+            time,
+            seed: None,
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..self
+        }
     }
 
     pub fn at(&self, t: f64) -> Point3 {
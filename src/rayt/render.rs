@@ -1,32 +1,111 @@
 use crate::rayt::camera::*;
 use crate::rayt::float3::*;
+use crate::rayt::hdr::*;
 use crate::rayt::ray::*;
-use image::{Rgb, RgbImage};
+use crate::rayt::sampler::*;
+use crate::rayt::tonemap::*;
+use image::{ImageFormat, ImageResult, Rgb, RgbImage};
 use rayon::prelude::*;
-use std::{fs, path::Path};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
-const IMAGE_WIDTH: u32 = 200;
-const IMAGE_HEIGHT: u32 = 200;
+pub const IMAGE_WIDTH: u32 = 200;
+pub const IMAGE_HEIGHT: u32 = 200;
 
-const SAMPLES_PER_PIXEL: usize = 8;
+pub const SAMPLES_PER_PIXEL: usize = 8;
 
-const GAMMA_FACTOR: f64 = 2.2;
-const MAX_RAY_BOUNCE_DEPTH: usize = 50;
+pub const GAMMA_FACTOR: f64 = 2.2;
+pub const MAX_RAY_BOUNCE_DEPTH: usize = 50;
 
-const OUTPUT_FILENAME: &str = "render.png";
-const BUCKUP_FILENAME: &str = "render_bak.png";
+pub const OUTPUT_FILENAME: &str = "render.png";
 
-fn backup() {
-    let output_path = Path::new(OUTPUT_FILENAME);
+// 描画の設定 (None の項目はシーンの値を使う)
+#[derive(Debug, Clone)]
+pub struct RenderConfig {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub spp: Option<usize>,
+    pub max_depth: usize,
+    pub exposure: f64,                  // 露出補正 (EV)
+    pub tonemap: ToneMap,               // トーンマッピングの方式
+    pub gamma: Option<f64>,             // None なら sRGB の変換式を使う
+    pub output: String,                 // .hdr/.pfm ならリニアな値をそのまま書き出す
+    pub hdr_output: Option<String>,     // output とは別に書き出す HDR 画像
+    pub backup: bool,                   // 既存の出力ファイルを *_bak にリネームする
+    pub seed: u64,                      // 乱数のシード (同じ値なら同じ画像になる)
+    pub sampler: SamplerKind,           // サンプル列の種類
+    pub projection: Option<Projection>, // 投影の方式 (None ならシーンの指定)
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            spp: None,
+            max_depth: MAX_RAY_BOUNCE_DEPTH,
+            exposure: 0.0,
+            tonemap: ToneMap::default(),
+            gamma: None,
+            output: OUTPUT_FILENAME.to_string(),
+            hdr_output: None,
+            backup: true,
+            seed: 0,
+            sampler: SamplerKind::default(),
+            projection: None,
+        }
+    }
+}
+
+impl RenderConfig {
+    // 蓄積した放射輝度を表示用の色に変換する (露出 -> トーンマッピング -> ガンマ)
+    pub fn to_display(&self, c: Color) -> Color {
+        let c = self.tonemap.apply(c * 2f64.powf(self.exposure));
+        match self.gamma {
+            Some(gamma) => c.gamma(gamma),
+            None => c.linear_to_srgb(),
+        }
+    }
+}
+
+// render.png -> render_bak.png
+fn backup_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}_bak.{}", stem, ext.to_string_lossy()),
+        None => format!("{}_bak", stem),
+    };
+    output.with_file_name(name)
+}
+
+fn backup(output: &str) -> io::Result<()> {
+    let output_path = Path::new(output);
     if output_path.exists() {
-        println!("backup {:?} -> {:?}", OUTPUT_FILENAME, BUCKUP_FILENAME);
-        fs::rename(OUTPUT_FILENAME, BUCKUP_FILENAME).unwrap();
+        let backup_path = backup_path(output_path);
+        println!("backup {:?} -> {:?}", output_path, backup_path);
+        fs::rename(output_path, &backup_path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("backup {:?} -> {:?}: {}", output_path, backup_path, e),
+            )
+        })?;
     }
+    Ok(())
+}
+
+// 出力先のファイルを全てバックアップする
+pub fn backup_outputs(config: &RenderConfig) -> io::Result<()> {
+    backup(&config.output)?;
+    if let Some(hdr_output) = &config.hdr_output {
+        backup(hdr_output)?;
+    }
+    Ok(())
 }
 
 pub trait SceneWithDepth {
-    fn camera(&self) -> Camera;
-    fn trace(&self, ray: Ray, depth: usize) -> Color;
+    fn view(&self) -> View;
+    fn trace(&self, ray: Ray, depth: usize, sampler: &mut dyn Sampler) -> Color;
     fn width(&self) -> u32 {
         IMAGE_WIDTH
     }
@@ -36,38 +115,121 @@ pub trait SceneWithDepth {
     fn spp(&self) -> usize {
         SAMPLES_PER_PIXEL
     }
-    fn aspect(&self) -> f64 {
-        self.width() as f64 / self.height() as f64
+}
+
+pub fn render_aa_with_depth(scene: impl SceneWithDepth + Sync) -> Result<(), String> {
+    render_with_config(&scene, &RenderConfig::default())
+}
+
+// 描画に必要な情報 (一括描画とプレビューで共有する)
+pub struct RenderContext<'a, S: ?Sized> {
+    scene: &'a S,
+    camera: Box<dyn Camera>,
+    pub width: u32,
+    pub height: u32,
+    pub spp: usize,
+    max_depth: usize,
+    seed: u64,
+    sampler: SamplerKind,
+}
+
+impl<'a, S> RenderContext<'a, S>
+where
+    S: SceneWithDepth + Sync + ?Sized,
+{
+    pub fn new(scene: &'a S, config: &RenderConfig) -> Self {
+        let width = config.width.unwrap_or_else(|| scene.width());
+        let height = config.height.unwrap_or_else(|| scene.height());
+        Self {
+            scene,
+            camera: config
+                .projection
+                .map_or_else(|| scene.view(), |p| scene.view().projection(p))
+                .camera(width as f64 / height as f64),
+            width,
+            height,
+            spp: config.spp.unwrap_or_else(|| scene.spp()),
+            max_depth: config.max_depth,
+            seed: config.seed,
+            sampler: config.sampler,
+        }
+    }
+
+    // ピクセル (x, y) の index 番目のサンプルを取る
+    pub fn sample(&self, x: u32, y: u32, index: usize) -> Color {
+        let pixel = y as u64 * self.width as u64 + x as u64;
+        let mut sampler = self.sampler.create(self.seed, pixel, index, self.spp);
+        let [rx, ry] = sampler.next_2d();
+        let u = (x as f64 + rx) / (self.width - 1) as f64;
+        let v = ((self.height - y - 1) as f64 + ry) / (self.height - 1) as f64;
+        match self.camera.ray(u, v, sampler.as_mut()) {
+            Some(ray) => self.scene.trace(ray, self.max_depth, sampler.as_mut()),
+            None => Color::zero(),
+        }
     }
 }
 
-pub fn render_aa_with_depth(scene: impl SceneWithDepth + Sync) {
-    // scene は複数スレッドから参照されるため、Syncマーカートレイトが必要
+// save_image で書き出せる拡張子か (.hdr/.pfm と 8bit の RGB を書ける形式)
+pub fn is_output_path(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    is_hdr_path(path)
+        || matches!(
+            ImageFormat::from_path(path),
+            Ok(ImageFormat::Png)
+                | Ok(ImageFormat::Jpeg)
+                | Ok(ImageFormat::Bmp)
+                | Ok(ImageFormat::Tiff)
+                | Ok(ImageFormat::Tga)
+                | Ok(ImageFormat::Pnm)
+        )
+}
 
-    backup();
+// 放射輝度を画像として保存する (pixels は左上から行順)
+pub fn save_image(
+    pixels: &[Color],
+    width: u32,
+    height: u32,
+    config: &RenderConfig,
+) -> ImageResult<()> {
+    if is_hdr_path(&config.output) {
+        save_hdr(pixels, width, height, &config.output)?;
+    } else {
+        let mut img = RgbImage::new(width, height);
+        for (pixel, color) in img.pixels_mut().zip(pixels) {
+            *pixel = Rgb(config.to_display(*color).to_rgb());
+        }
+        img.save(&config.output)?;
+    }
 
-    let camera = scene.camera();
+    if let Some(hdr_output) = &config.hdr_output {
+        save_hdr(pixels, width, height, hdr_output)?;
+    }
+    Ok(())
+}
 
-    let mut img = RgbImage::new(scene.width(), scene.height());
-    img.enumerate_pixels_mut()
-        .collect::<Vec<(u32, u32, &mut Rgb<u8>)>>()
-        .par_iter_mut()
-        .for_each(|(x, y, pixel)| {
-            let mut pixel_color = (0..scene.spp()).into_iter().fold(Color::zero(), |acc, _| {
-                let [rx, ry, _] = Float3::random().to_array();
-                let u = (*x as f64 + rx) / (scene.width() - 1) as f64;
-                let v = ((scene.height() - *y - 1) as f64 + ry) / (scene.height() - 1) as f64;
-                let ray = camera.ray(u, v);
-                acc + scene.trace(ray, MAX_RAY_BOUNCE_DEPTH)
-            });
+// 描画できなかった場合は理由を返す
+pub fn render_with_config<S>(scene: &S, config: &RenderConfig) -> Result<(), String>
+where
+    S: SceneWithDepth + Sync + ?Sized,
+{
+    // scene は複数スレッドから参照されるため、Syncマーカートレイトが必要
 
-            pixel_color /= scene.spp() as f64;
+    // バックアップできなければ上書きせずにやめる
+    if config.backup {
+        backup_outputs(config).map_err(|e| e.to_string())?;
+    }
 
-            let rgb = pixel_color.gamma(GAMMA_FACTOR).to_rgb();
+    let ctx = RenderContext::new(scene, config);
+    let width = ctx.width;
+    let pixels = (0..ctx.width * ctx.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let pixel_color = (0..ctx.spp).fold(Color::zero(), |acc, s| acc + ctx.sample(x, y, s));
+            pixel_color / ctx.spp as f64
+        })
+        .collect::<Vec<_>>();
 
-            pixel[0] = rgb[0];
-            pixel[1] = rgb[1];
-            pixel[2] = rgb[2];
-        });
-    img.save(OUTPUT_FILENAME).unwrap();
+    save_image(&pixels, ctx.width, ctx.height, config)
+        .map_err(|e| format!("failed to save {:?}: {}", config.output, e))
 }
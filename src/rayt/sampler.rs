@@ -0,0 +1,400 @@
+use std::str::FromStr;
+
+// サンプル値の列
+// 次元ごとに値を取り出す。カメラ・BSDF・光源のサンプリングは呼び出し順に次元を消費する
+pub trait Sampler {
+    // [0, 1) の値
+    fn next_f64(&mut self) -> f64;
+
+    // 2次元で分布の良い組 (画素内の位置や方向の生成に使う)
+    fn next_2d(&mut self) -> [f64; 2] {
+        [self.next_f64(), self.next_f64()]
+    }
+}
+
+// サンプラーの種類
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplerKind {
+    #[default]
+    Independent, // 一様乱数
+    Stratified, // 次元ごとに spp 個の層に分けて1つずつ取る
+    Halton,     // 画素ごとにずらした Halton 列
+    Sobol,      // Owen スクランブルした Sobol 列
+}
+
+impl SamplerKind {
+    pub const NAMES: &'static [&'static str] = &["independent", "stratified", "halton", "sobol"];
+
+    // (シード, ピクセル, サンプル番号) から状態を決めるので、
+    // スレッド数や実行順に関係なく同じ描画結果になる
+    pub fn create(&self, seed: u64, pixel: u64, index: usize, spp: usize) -> Box<dyn Sampler> {
+        let pixel_seed = hash(seed, pixel);
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(pixel_seed, index)),
+            SamplerKind::Stratified => {
+                Box::new(StratifiedSampler::new(pixel_seed, index, spp.max(1)))
+            }
+            SamplerKind::Halton => Box::new(HaltonSampler::new(pixel_seed, index)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(pixel_seed, index)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!(
+                "unknown sampler {:?} (expected one of: {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// SplitMix64 の混ぜ合わせ関数
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn hash(a: u64, b: u64) -> u64 {
+    mix(mix(a) ^ b)
+}
+
+// ハッシュ値から [0, 1) の値を作る
+fn to_unit(x: u64) -> f64 {
+    (x >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+// 値の列から決まる [0, 1) の値
+// サンプラーを受け取れない場所 (形状の交差判定など) で、実行順によらない乱数として使う
+pub fn hash_unit(values: &[u64]) -> f64 {
+    to_unit(values.iter().fold(0, |acc, &x| hash(acc, x)))
+}
+
+// 乱数列 (SplitMix64)
+#[derive(Debug, Clone)]
+pub struct SplitMix {
+    state: u64,
+}
+
+impl SplitMix {
+    const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    pub fn new(seed: u64) -> Self {
+        Self { state: mix(seed) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(Self::GOLDEN_GAMMA);
+        mix(self.state)
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        to_unit(self.next_u64())
+    }
+}
+
+pub struct IndependentSampler {
+    rng: SplitMix,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64, index: usize) -> Self {
+        Self {
+            rng: SplitMix::new(hash(seed, index as u64)),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn next_f64(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+}
+
+// 各次元を spp 個の層に分け、サンプル番号を次元ごとにシャッフルした層に割り当てる
+// (次元間の相関をなくしたラテン超方格)
+pub struct StratifiedSampler {
+    rng: SplitMix,
+    seed: u64,
+    index: usize,
+    spp: usize,
+    dim: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, index: usize, spp: usize) -> Self {
+        Self {
+            rng: SplitMix::new(hash(seed, index as u64)),
+            seed,
+            index,
+            spp,
+            dim: 0,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn next_f64(&mut self) -> f64 {
+        let n = self.spp as u32;
+        let i = (self.index % self.spp) as u32;
+        let stratum = permute(i, n, hash(self.seed, self.dim) as u32);
+        self.dim += 1;
+        (stratum as f64 + self.rng.next_f64()) / n as f64
+    }
+}
+
+// [0, n) の置換 (Kensler, "Correlated Multi-Jittered Sampling")
+fn permute(mut i: u32, n: u32, p: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    i.wrapping_add(p) % n
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// 基数 base の逆基数変換 (Halton 列の1次元分)
+fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let inv_base = (base as f64).recip();
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    result
+}
+
+// 画素ごとに各次元をランダムにずらす (Cranley-Patterson rotation)
+// 素数表を使い切った次元は一様乱数で埋める
+pub struct HaltonSampler {
+    rng: SplitMix,
+    seed: u64,
+    index: u64,
+    dim: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64, index: usize) -> Self {
+        Self {
+            rng: SplitMix::new(hash(seed, index as u64)),
+            seed,
+            index: index as u64,
+            dim: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn next_f64(&mut self) -> f64 {
+        let dim = self.dim;
+        self.dim += 1;
+        match PRIMES.get(dim) {
+            Some(&base) => {
+                let offset = to_unit(hash(self.seed, dim as u64));
+                (radical_inverse(base, self.index) + offset).fract()
+            }
+            None => self.rng.next_f64(),
+        }
+    }
+}
+
+// Sobol 列の最初の2次元の生成行列 (Joe & Kuo)
+const SOBOL_MATRICES: [[u32; 32]; 2] = [sobol_matrix(0, 0, &[]), sobol_matrix(1, 0, &[1])];
+
+// s: 原始多項式の次数, a: 係数, m: 初期方向数
+const fn sobol_matrix(s: usize, a: u32, m: &[u32]) -> [u32; 32] {
+    let mut v = [0u32; 32];
+    let mut i = 0;
+    while i < 32 {
+        if s == 0 {
+            v[i] = 1 << (31 - i);
+        } else if i < s {
+            v[i] = m[i] << (31 - i);
+        } else {
+            v[i] = v[i - s] ^ (v[i - s] >> s);
+            let mut k = 1;
+            while k < s {
+                v[i] ^= ((a >> (s - 1 - k)) & 1) * v[i - k];
+                k += 1;
+            }
+        }
+        i += 1;
+    }
+    v
+}
+
+fn sobol(index: u32, dim: usize) -> u32 {
+    let mut x = 0;
+    let mut index = index;
+    let mut bit = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            x ^= SOBOL_MATRICES[dim][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+    x
+}
+
+// Laine-Karras の置換 (下位ビットから上位ビットへの Owen スクランブル)
+fn laine_karras(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras(x.reverse_bits(), seed).reverse_bits()
+}
+
+// 2次元ずつ独立にスクランブルした Sobol 列を並べる (Burley, "Practical Hash-based Owen Scrambling")
+pub struct SobolSampler {
+    seed: u64,
+    index: u32,
+    dim: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64, index: usize) -> Self {
+        Self {
+            seed,
+            index: index as u32,
+            dim: 0,
+        }
+    }
+
+    fn sample(&mut self, dims: usize) -> [f64; 2] {
+        let seed = hash(self.seed, self.dim);
+        self.dim += 1;
+        let index = nested_uniform_scramble(self.index, seed as u32);
+        let mut x = [0.0; 2];
+        for (d, x) in x.iter_mut().enumerate().take(dims) {
+            let s = hash(seed, d as u64 + 1) as u32;
+            *x = nested_uniform_scramble(sobol(index, d), s) as f64 / (1u64 << 32) as f64;
+        }
+        x
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn next_f64(&mut self) -> f64 {
+        self.sample(1)[0]
+    }
+
+    fn next_2d(&mut self) -> [f64; 2] {
+        self.sample(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn samples(kind: SamplerKind, seed: u64, pixel: u64, index: usize) -> Vec<f64> {
+        let mut s = kind.create(seed, pixel, index, 16);
+        let mut v: Vec<f64> = (0..40).map(|_| s.next_f64()).collect();
+        v.extend(s.next_2d().iter());
+        v
+    }
+
+    #[test]
+    fn test_reproducible() {
+        for kind in KINDS.iter().copied() {
+            let a = samples(kind, 1, 42, 3);
+            assert_eq!(a, samples(kind, 1, 42, 3));
+            assert_ne!(a, samples(kind, 1, 42, 4));
+            assert_ne!(a, samples(kind, 1, 43, 3));
+            assert_ne!(a, samples(kind, 2, 42, 3));
+            assert!(a.iter().all(|x| (0.0..1.0).contains(x)), "{:?}", kind);
+        }
+    }
+
+    // spp 個のサンプルが各次元の spp 個の層に1つずつ入る
+    #[test]
+    fn test_stratification() {
+        let spp = 16;
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol].iter() {
+            let mut strata = vec![[0; 16]; 4];
+            for index in 0..spp {
+                let mut s = kind.create(7, 5, index, spp);
+                for count in strata.iter_mut() {
+                    count[(s.next_f64() * spp as f64) as usize] += 1;
+                }
+            }
+            assert!(strata.iter().flatten().all(|&c| c == 1), "{:?}", kind);
+        }
+
+        // Sobol の2次元は (0, 4, 2)-net: 4x4 の各マスに1つずつ入る
+        let mut cells = [0; 16];
+        for index in 0..spp {
+            let [x, y] = SamplerKind::Sobol.create(7, 5, index, spp).next_2d();
+            cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        assert_eq!(0.5, radical_inverse(2, 1));
+        assert_eq!(0.75, radical_inverse(2, 3));
+        assert_eq!(2.0 / 3.0 + 1.0 / 9.0, radical_inverse(3, 5));
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Ok(SamplerKind::Sobol), "sobol".parse());
+        assert!("random".parse::<SamplerKind>().is_err());
+    }
+}
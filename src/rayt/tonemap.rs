@@ -0,0 +1,90 @@
+use crate::rayt::float3::*;
+use std::str::FromStr;
+
+// 放射輝度を表示可能な [0, 1] の範囲に収める
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    #[default]
+    Clamp, // 1.0 を超える値は切り捨てる
+    Reinhard,              // x / (1 + x)
+    ReinhardExtended(f64), // 指定した白色点が 1.0 になる Reinhard
+    Aces,                  // ACES filmic (Narkowicz の近似式)
+}
+
+impl ToneMap {
+    pub const NAMES: &'static [&'static str] =
+        &["clamp", "reinhard", "reinhard-extended[:WHITE]", "aces"];
+
+    const DEFAULT_WHITE: f64 = 4.0;
+
+    pub fn apply(&self, c: Color) -> Color {
+        let f = |x: f64| match *self {
+            ToneMap::Clamp => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::ReinhardExtended(white) => x * (1.0 + x / white.powi(2)) / (1.0 + x),
+            ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        };
+        Color::new(f(c.x()), f(c.y()), f(c.z())).saturate()
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        match (name, param) {
+            ("clamp", None) => Ok(ToneMap::Clamp),
+            ("reinhard", None) => Ok(ToneMap::Reinhard),
+            ("reinhard-extended", None) => Ok(ToneMap::ReinhardExtended(Self::DEFAULT_WHITE)),
+            ("reinhard-extended", Some(white)) => match white.parse::<f64>() {
+                Ok(white) if white > 0.0 => Ok(ToneMap::ReinhardExtended(white)),
+                _ => Err(format!("invalid white point {:?}", white)),
+            },
+            ("aces", None) => Ok(ToneMap::Aces),
+            _ => Err(format!(
+                "unknown tone mapping {:?} (expected one of: {})",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let c = Color::new(0.0, 1.0, 15.0);
+        assert_eq!(Color::new(0.0, 1.0, 1.0), ToneMap::Clamp.apply(c));
+        assert_eq!(
+            Color::new(0.0, 0.5, 15.0 / 16.0),
+            ToneMap::Reinhard.apply(c)
+        );
+
+        // 白色点はちょうど 1.0 になる
+        let white = ToneMap::ReinhardExtended(4.0).apply(Color::full(4.0));
+        assert!((white.x() - 1.0).abs() < 1e-12);
+
+        let aces = ToneMap::Aces.apply(c);
+        assert_eq!(0.0, aces.x());
+        assert!(aces.y() > 0.7 && aces.y() < 0.9);
+        assert_eq!(1.0, aces.z());
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Ok(ToneMap::Aces), "aces".parse());
+        assert_eq!(
+            Ok(ToneMap::ReinhardExtended(8.0)),
+            "reinhard-extended:8".parse()
+        );
+        assert!("reinhard-extended:0".parse::<ToneMap>().is_err());
+        assert!("filmic".parse::<ToneMap>().is_err());
+    }
+}
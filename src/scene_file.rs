@@ -0,0 +1,824 @@
+// JSON 形式のシーン記述ファイルの読み込み
+//
+// {
+//   "image": { "width": 200, "height": 200, "spp": 8 },
+//   "camera": { "lookfrom": [278, 278, -800], "lookat": [278, 278, 0], "vup": [0, 1, 0], "vfov": 40,
+//               "aperture": 0, "focus_distance": 800, "blades": 6, "shutter": [0, 1],
+//               "projection": "perspective" },
+//   "background": [0, 0, 0],
+//   "environment": { "file": "sky.hdr", "scale": 1, "rotate": { "axis": [0, 1, 0], "angle": 90 } },
+//   "textures": { "white": { "type": "color", "color": [0.73, 0.73, 0.73] } },
+//   "materials": { "white": { "type": "lambertian", "texture": "white" } },
+//   "shapes": [
+//     { "type": "box", "p0": [0, 0, 0], "p1": [165, 330, 165], "material": "white",
+//       "transform": [{ "rotate": { "axis": [0, 1, 0], "angle": 15 } }, { "translate": [265, 0, 295] }] }
+//   ],
+//   "lights": [ { "type": "rect_xz", "x0": 213, "x1": 343, "y0": 227, "y1": 332, "k": 554 } ]
+// }
+//
+// environment を指定すると background の代わりに画像の環境光で照らし、光源としてもサンプリングする。
+//
+// 動く物体 ("moving_sphere" や from/to を指定した translate/rotate) は時刻 0 から 1 の間で動き、
+// カメラの shutter の間の時刻で光線を飛ばすとぶれて写る。
+//
+// 粗い金属は { "type": "conductor", "preset": "gold", "roughness": 0.3 } (preset の代わりに
+// 複素屈折率 "eta" と "k" も指定できる)、粗いガラスは { "type": "rough_dielectric", "ri": 1.5, "roughness": 0.3 }。
+//
+// { "type": "principled", "base_color": [0.8, 0.1, 0.1], "metallic": 0, "roughness": 0.5, "specular": 0.5,
+//   "specular_tint": 0, "sheen": 0, "clearcoat": 0, "transmission": 0, "anisotropic": 0 } は全部入りの材質で、
+// base_color 以外は省略でき、数値の代わりにテクスチャも書ける (R の値を使う)。
+//
+// "fog": { "density": 0.01, "color": [1, 1, 1], "g": 0 } を指定すると、シーン全体を一様な霧で満たす。
+// 形状に "density" を書くと内側が煙のような媒質になり、材質は { "type": "isotropic", "texture": ... } か
+// { "type": "henyey_greenstein", "texture": ..., "g": 0.5 } の位相関数に限る (境界は凸形状)。density は正の値。
+//
+// 形状には "normal_map" (接空間の法線マップ) と { "bump_map": { "height": テクスチャ, "scale": 0.1 } }
+// を指定でき、シェーディング法線だけを曲げる。法線マップの画像は { "type": "image", "linear": true }
+// としてガンマを外さずに読む。
+//
+// テクスチャ・材質は名前で参照するほか、その場に直接書くこともできる。
+// 色は [r, g, b] か "#rrggbb" で指定する。
+
+use crate::rayt::json::*;
+use crate::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Json(JsonError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+impl From<JsonError> for SceneError {
+    fn from(e: JsonError) -> Self {
+        SceneError::Json(e)
+    }
+}
+
+// テクスチャの名前参照をたどる深さの上限 (循環参照の検出用)
+const MAX_TEXTURE_DEPTH: usize = 32;
+
+pub struct FileScene {
+    world: BvhNode,
+    light: Option<Arc<dyn Shape>>,
+    background: Color,
+    environment: Option<EnvironmentLight>, // 背景の代わりの環境光
+    view: View,
+    width: u32,
+    height: u32,
+    spp: usize,
+}
+
+impl FileScene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path)?;
+        Self::parse(&src, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    // dir はシーン内の相対パス (OBJ ファイルなど) の基準
+    pub fn parse(src: &str, dir: &Path) -> Result<Self, SceneError> {
+        let root = Json::parse(src)?;
+        Ok(Loader::new(&root, dir)?.scene(&root)?)
+    }
+}
+
+impl Scene for FileScene {
+    fn view(&self) -> View {
+        self.view.clone()
+    }
+
+    fn world(&self) -> &dyn Shape {
+        &self.world
+    }
+
+    fn light(&self) -> Option<&Arc<dyn Shape>> {
+        self.light.as_ref()
+    }
+
+    fn background(&self, d: Vec3) -> Color {
+        match &self.environment {
+            Some(environment) => environment.radiance(d),
+            None => self.background,
+        }
+    }
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn spp(&self) -> usize {
+        self.spp
+    }
+}
+
+struct Loader<'a> {
+    dir: &'a Path,
+    textures: HashMap<&'a str, &'a Json>,
+    materials: HashMap<&'a str, (&'a Json, Arc<dyn Material>)>,
+}
+
+impl<'a> Loader<'a> {
+    fn new(root: &'a Json, dir: &'a Path) -> Result<Self, JsonError> {
+        let mut loader = Self {
+            dir,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+        };
+
+        if let Some(textures) = root.get("textures") {
+            for (name, texture) in textures.as_object()? {
+                loader.textures.insert(name, texture);
+            }
+        }
+        // 材質は共有されるので先に作っておく
+        if let Some(materials) = root.get("materials") {
+            for (name, material) in materials.as_object()? {
+                let built = loader.material(material)?;
+                loader.materials.insert(name, (material, built));
+            }
+        }
+        Ok(loader)
+    }
+
+    fn scene(&self, root: &Json) -> Result<FileScene, JsonError> {
+        let (width, height, spp) = if let Some(image) = root.get("image") {
+            (
+                // 幅・高さは画素の位置を (n - 1) で割るので 2 以上
+                opt(image, "width", |x| at_least(x, 2))?.map_or(IMAGE_WIDTH, |x| x as u32),
+                opt(image, "height", |x| at_least(x, 2))?.map_or(IMAGE_HEIGHT, |x| x as u32),
+                opt(image, "spp", |x| at_least(x, 1))?.unwrap_or(SAMPLES_PER_PIXEL),
+            )
+        } else {
+            (IMAGE_WIDTH, IMAGE_HEIGHT, SAMPLES_PER_PIXEL)
+        };
+
+        let mut world = ShapeList::new();
+        let shapes = root.field("shapes")?;
+        for shape in shapes.as_array()? {
+            world.push(self.shape(shape, false)?);
+        }
+        if world.objects.is_empty() {
+            return Err(JsonError::new(shapes.line, "scene has no shapes"));
+        }
+        let view = view(root.field("camera")?)?;
+        if let Some(fog) = root.get("fog") {
+            let fog = fog_shape(fog, world.bounding_box(), view.lookfrom)?;
+            world.push(fog);
+        }
+
+        let mut light = ShapeList::new();
+        if let Some(lights) = root.get("lights") {
+            for shape in lights.as_array()? {
+                light.push(self.shape(shape, true)?);
+            }
+        }
+        let environment = opt(root, "environment", |e| self.environment(e))?;
+        if let Some(environment) = &environment {
+            light.push(Box::new(environment.clone()));
+        }
+
+        Ok(FileScene {
+            world: BvhNode::from_list(world),
+            light: if light.objects.is_empty() {
+                None
+            } else {
+                Some(Arc::new(light))
+            },
+            background: opt(root, "background", color)?.unwrap_or_else(Color::zero),
+            environment,
+            view,
+            width,
+            height,
+            spp,
+        })
+    }
+
+    // { "file": "sky.hdr", "scale": 1, "rotate": { "axis": [x, y, z], "angle": deg } }
+    fn environment(&self, json: &Json) -> Result<EnvironmentLight, JsonError> {
+        let file = json.field("file")?;
+        let map = EnvironmentMap::load(self.dir.join(file.as_str()?))
+            .map_err(|e| JsonError::new(file.line, e.to_string()))?;
+        let mut light = EnvironmentLight::new(Arc::new(map))
+            .scale(opt(json, "scale", Json::as_f64)?.unwrap_or(1.0));
+        if let Some(rotate) = json.get("rotate") {
+            let (axis, angle) = rotation(rotate)?;
+            light = light.rotate(axis, angle);
+        }
+        Ok(light)
+    }
+
+    fn texture(&self, json: &Json, depth: usize) -> Result<Box<dyn Texture>, JsonError> {
+        if depth > MAX_TEXTURE_DEPTH {
+            return Err(JsonError::new(
+                json.line,
+                "texture references are nested too deeply",
+            ));
+        }
+
+        match &json.value {
+            JsonValue::String(name) if !name.starts_with('#') => {
+                let texture = self.textures.get(name.as_str()).ok_or_else(|| {
+                    JsonError::new(json.line, format!("unknown texture {:?}", name))
+                })?;
+                self.texture(texture, depth + 1)
+            }
+            JsonValue::String(_) | JsonValue::Array(_) => {
+                Ok(Box::new(ColorTexture::new(color(json)?)))
+            }
+            _ => match kind(json)? {
+                "color" => Ok(Box::new(ColorTexture::new(color(json.field("color")?)?))),
+                "checker" => Ok(Box::new(CheckerTexture::new(
+                    self.texture(json.field("odd")?, depth + 1)?,
+                    self.texture(json.field("even")?, depth + 1)?,
+                    json.field("freq")?.as_f64()?,
+                ))),
+                "image" => {
+                    let file = json.field("file")?;
+                    let path = self.dir.join(file.as_str()?);
+                    let texture = if opt(json, "linear", Json::as_bool)?.unwrap_or(false) {
+                        ImageTexture::open_linear(&path)
+                    } else {
+                        ImageTexture::open(&path)
+                    };
+                    let texture = texture.map_err(|e| {
+                        JsonError::new(file.line, format!("{}: {}", path.display(), e))
+                    })?;
+                    Ok(Box::new(
+                        texture
+                            .filter(
+                                opt(json, "filter", texture_filter)?
+                                    .unwrap_or(TextureFilter::Bilinear),
+                            )
+                            .wrap(opt(json, "wrap", wrap_mode)?.unwrap_or(WrapMode::Repeat)),
+                    ))
+                }
+                "noise" => {
+                    let (perlin, scale) = noise(json)?;
+                    let (c0, c1) = (color(json.field("color0")?)?, color(json.field("color1")?)?);
+                    Ok(Box::new(NoiseTexture::new(perlin, scale, c0, c1)))
+                }
+                "marble" => {
+                    let (perlin, scale) = noise(json)?;
+                    let (vein, base) = (color(json.field("vein")?)?, color(json.field("base")?)?);
+                    Ok(Box::new(MarbleTexture::new(perlin, scale, vein, base)))
+                }
+                "wood" => {
+                    let (perlin, scale) = noise(json)?;
+                    let (light, dark) = (color(json.field("light")?)?, color(json.field("dark")?)?);
+                    Ok(Box::new(WoodTexture::new(perlin, scale, light, dark)))
+                }
+                "cloudy" => {
+                    let (perlin, scale) = noise(json)?;
+                    let (sky, cloud) = (color(json.field("sky")?)?, color(json.field("cloud")?)?);
+                    Ok(Box::new(CloudyTexture::new(perlin, scale, sky, cloud)))
+                }
+                other => Err(unknown(json, "texture", other)),
+            },
+        }
+    }
+
+    // 数値か、値を R に入れたテクスチャ
+    fn parameter(&self, json: &Json) -> Result<Box<dyn Texture>, JsonError> {
+        match json.value {
+            JsonValue::Number(x) => Ok(Box::new(ColorTexture::new(Color::full(x)))),
+            _ => self.texture(json, 0),
+        }
+    }
+
+    // 媒質の位相関数として使える材質か (名前で参照された材質は定義を見る)
+    fn is_phase(&self, json: &Json) -> bool {
+        let json = match &json.value {
+            JsonValue::String(name) => match self.materials.get(name.as_str()) {
+                Some((json, _)) => *json,
+                None => return false,
+            },
+            _ => json,
+        };
+        matches!(kind(json), Ok("isotropic") | Ok("henyey_greenstein"))
+    }
+
+    fn material(&self, json: &Json) -> Result<Arc<dyn Material>, JsonError> {
+        if let JsonValue::String(name) = &json.value {
+            return self
+                .materials
+                .get(name.as_str())
+                .map(|(_, material)| Arc::clone(material))
+                .ok_or_else(|| JsonError::new(json.line, format!("unknown material {:?}", name)));
+        }
+
+        let material: Arc<dyn Material> = match kind(json)? {
+            "lambertian" => Arc::new(Lambertian::new(self.texture(json.field("texture")?, 0)?)),
+            "metal" => Arc::new(Metal::new(
+                self.texture(json.field("texture")?, 0)?,
+                opt(json, "fuzz", Json::as_f64)?.unwrap_or(0.0),
+            )),
+            "dielectric" => Arc::new(Dielectric::new(json.field("ri")?.as_f64()?)),
+            "conductor" => {
+                let roughness = opt(json, "roughness", Json::as_f64)?.unwrap_or(0.0);
+                match json.get("preset") {
+                    Some(preset) => Arc::new(Conductor::preset(
+                        preset
+                            .as_str()?
+                            .parse()
+                            .map_err(|e| JsonError::new(preset.line, e))?,
+                        roughness,
+                    )),
+                    None => Arc::new(Conductor::new(
+                        color(json.field("eta")?)?,
+                        color(json.field("k")?)?,
+                        roughness,
+                    )),
+                }
+            }
+            "rough_dielectric" => Arc::new(RoughDielectric::new(
+                json.field("ri")?.as_f64()?,
+                opt(json, "roughness", Json::as_f64)?.unwrap_or(0.0),
+            )),
+            "principled" => {
+                let mut material = Principled::new(self.texture(json.field("base_color")?, 0)?);
+                type Setter = fn(Principled, Box<dyn Texture>) -> Principled;
+                let setters: [(&str, Setter); 8] = [
+                    ("metallic", Principled::metallic),
+                    ("roughness", Principled::roughness),
+                    ("specular", Principled::specular),
+                    ("specular_tint", Principled::specular_tint),
+                    ("sheen", Principled::sheen),
+                    ("clearcoat", Principled::clearcoat),
+                    ("transmission", Principled::transmission),
+                    ("anisotropic", Principled::anisotropic),
+                ];
+                for (key, set) in setters.iter() {
+                    if let Some(value) = opt(json, key, |v| self.parameter(v))? {
+                        material = set(material, value);
+                    }
+                }
+                Arc::new(material)
+            }
+            "isotropic" => Arc::new(Isotropic::new(self.texture(json.field("texture")?, 0)?)),
+            "henyey_greenstein" => Arc::new(HenyeyGreenstein::new(
+                self.texture(json.field("texture")?, 0)?,
+                opt(json, "g", Json::as_f64)?.unwrap_or(0.0),
+            )),
+            "diffuse_light" => {
+                Arc::new(DiffuseLight::new(self.texture(json.field("texture")?, 0)?))
+            }
+            other => return Err(unknown(json, "material", other)),
+        };
+        Ok(material)
+    }
+
+    // 光源リストの形状は材質を省略できる (サンプリングにしか使わないため)
+    fn shape(&self, json: &Json, is_light: bool) -> Result<Box<dyn Shape>, JsonError> {
+        let material = opt(json, "material", |m| self.material(m))?;
+
+        // OBJ は材質を省略すると MTL の材質を使う
+        if kind(json)? == "obj" {
+            let file = json.field("file")?;
+            let model = ObjModel::load(self.dir.join(file.as_str()?))
+                .map_err(|e| JsonError::new(file.line, e.to_string()))?;
+            let builder = ShapeBuilder::new().shape(model.into_shape(material));
+            return self.transforms(self.surface(builder, json)?, json);
+        }
+
+        let material = match material {
+            Some(material) => material,
+            None if is_light => {
+                Arc::new(Lambertian::new(Box::new(ColorTexture::new(Color::zero()))))
+            }
+            None => return Err(JsonError::new(json.line, "missing field \"material\"")),
+        };
+        let builder = ShapeBuilder::new().material(Arc::clone(&material));
+
+        let f = |key| -> Result<f64, JsonError> { json.field(key)?.as_f64() };
+        let builder = match kind(json)? {
+            "sphere" => builder.sphere(vec3(json.field("center")?)?, f("radius")?),
+            "moving_sphere" => builder.moving_sphere(
+                vec3(json.field("center0")?)?,
+                vec3(json.field("center1")?)?,
+                f("radius")?,
+            ),
+            "rect_xy" => builder.rect_xy(f("x0")?, f("x1")?, f("y0")?, f("y1")?, f("k")?),
+            "rect_xz" => builder.rect_xz(f("x0")?, f("x1")?, f("y0")?, f("y1")?, f("k")?),
+            "rect_yz" => builder.rect_yz(f("x0")?, f("x1")?, f("y0")?, f("y1")?, f("k")?),
+            "box" => builder.box3d(vec3(json.field("p0")?)?, vec3(json.field("p1")?)?),
+            "triangle" => builder.triangle(
+                vec3(json.field("p0")?)?,
+                vec3(json.field("p1")?)?,
+                vec3(json.field("p2")?)?,
+            ),
+            "mesh" => {
+                let (mesh, faces) = mesh(json)?;
+                builder.triangle_mesh(mesh, faces)
+            }
+            other => return Err(unknown(json, "shape", other)),
+        };
+        // density を指定すると形状の内側が媒質になり、材質は位相関数として使う
+        let builder = match json.get("density") {
+            Some(density) => {
+                let density = positive(density)?;
+                let phase = json.field("material")?;
+                if !self.is_phase(phase) {
+                    return Err(JsonError::new(
+                        phase.line,
+                        "a shape with \"density\" needs an isotropic or henyey_greenstein material",
+                    ));
+                }
+                builder.constant_medium(density, material)
+            }
+            None => builder,
+        };
+
+        self.transforms(self.surface(builder, json)?, json)
+    }
+
+    // "normal_map": テクスチャ / "bump_map": { "height": テクスチャ, "scale": 1 }
+    // 変換より前に、形状のテクスチャ座標の向きで法線を曲げる
+    fn surface(&self, builder: ShapeBuilder, json: &Json) -> Result<ShapeBuilder, JsonError> {
+        let builder = match json.get("normal_map") {
+            Some(map) => builder.normal_map(self.texture(map, 0)?),
+            None => builder,
+        };
+        Ok(match json.get("bump_map") {
+            Some(bump) => builder.bump_map(
+                self.texture(bump.field("height")?, 0)?,
+                opt(bump, "scale", Json::as_f64)?.unwrap_or(1.0),
+            ),
+            None => builder,
+        })
+    }
+
+    fn transforms(
+        &self,
+        mut builder: ShapeBuilder,
+        json: &Json,
+    ) -> Result<Box<dyn Shape>, JsonError> {
+        if let Some(transforms) = json.get("transform") {
+            for transform in transforms.as_array()? {
+                builder = self.transform(builder, transform)?;
+            }
+        }
+        Ok(builder.build())
+    }
+
+    // "flip_face" / { "translate": [x, y, z] } / { "rotate": { "axis": [x, y, z], "angle": deg } }
+    // translate と rotate は { "from": ..., "to": ... } と書くと時刻 0 から 1 の間で動く
+    fn transform(&self, builder: ShapeBuilder, json: &Json) -> Result<ShapeBuilder, JsonError> {
+        if let JsonValue::String(name) = &json.value {
+            return match name.as_str() {
+                "flip_face" => Ok(builder.flip_face()),
+                other => Err(unknown(json, "transform", other)),
+            };
+        }
+
+        match json.as_object()? {
+            [(name, value)] => match name.as_str() {
+                "translate" if value.get("from").is_some() => Ok(builder
+                    .moving_translate(vec3(value.field("from")?)?, vec3(value.field("to")?)?)),
+                "translate" => Ok(builder.translate(vec3(value)?)),
+                "rotate" if value.get("from").is_some() => {
+                    let (axis0, angle0) = rotation(value.field("from")?)?;
+                    let (axis1, angle1) = rotation(value.field("to")?)?;
+                    Ok(builder.moving_rotate(axis0, angle0, axis1, angle1))
+                }
+                "rotate" => {
+                    let (axis, angle) = rotation(value)?;
+                    Ok(builder.rotate(axis, angle))
+                }
+                "flip_face" if value.as_bool()? => Ok(builder.flip_face()),
+                "flip_face" => Ok(builder),
+                other => Err(unknown(json, "transform", other)),
+            },
+            _ => Err(JsonError::new(
+                json.line,
+                "transform must be an object with exactly one key",
+            )),
+        }
+    }
+}
+
+// { "density": 0.01, "color": [1, 1, 1], "g": 0 }
+// 物体とカメラを囲む球の中を一様な霧で満たす (ShapeBuilder::fog)
+fn fog_shape(json: &Json, bbox: AABB, lookfrom: Point3) -> Result<Box<dyn Shape>, JsonError> {
+    let builder = ShapeBuilder::new()
+        .color_texture(opt(json, "color", color)?.unwrap_or_else(Color::one))
+        .henyey_greenstein(opt(json, "g", Json::as_f64)?.unwrap_or(0.0));
+    Ok(builder
+        .fog(bbox, lookfrom, positive(json.field("density")?)?)
+        .build())
+}
+
+fn kind(json: &Json) -> Result<&str, JsonError> {
+    json.field("type")?.as_str()
+}
+
+fn unknown(json: &Json, what: &str, name: &str) -> JsonError {
+    JsonError::new(json.line, format!("unknown {} type {:?}", what, name))
+}
+
+fn at_least(json: &Json, min: usize) -> Result<usize, JsonError> {
+    let x = json.as_usize()?;
+    if x >= min {
+        Ok(x)
+    } else {
+        Err(JsonError::new(
+            json.line,
+            format!("expected an integer of at least {}, found {}", min, x),
+        ))
+    }
+}
+
+fn positive(json: &Json) -> Result<f64, JsonError> {
+    let x = json.as_f64()?;
+    if x > 0.0 {
+        Ok(x)
+    } else {
+        Err(JsonError::new(
+            json.line,
+            format!("expected a positive number, found {}", x),
+        ))
+    }
+}
+
+fn opt<'a, T>(
+    json: &'a Json,
+    key: &str,
+    f: impl Fn(&'a Json) -> Result<T, JsonError>,
+) -> Result<Option<T>, JsonError> {
+    json.get(key).map(f).transpose()
+}
+
+// { "positions": [[x, y, z], ...], "indices": [[i0, i1, i2], ...], "normals": [...], "uvs": [[u, v], ...] }
+// normals と uvs は省略可能で、positions と同じインデックスで参照する
+fn mesh(json: &Json) -> Result<(Arc<MeshData>, Vec<Face>), JsonError> {
+    let positions = json
+        .field("positions")?
+        .as_array()?
+        .iter()
+        .map(vec3)
+        .collect::<Result<Vec<_>, _>>()?;
+    let normals: Option<Vec<Vec3>> = opt(json, "normals", |normals| {
+        normals.as_array()?.iter().map(vec3).collect()
+    })?;
+    let uvs: Option<Vec<[f64; 2]>> = opt(json, "uvs", |uvs| {
+        uvs.as_array()?
+            .iter()
+            .map(|uv| match uv.as_array()? {
+                [u, v] => Ok([u.as_f64()?, v.as_f64()?]),
+                _ => Err(JsonError::new(uv.line, "expected [u, v]")),
+            })
+            .collect()
+    })?;
+
+    if let Some(normals) = &normals {
+        if normals.len() != positions.len() {
+            return Err(JsonError::new(
+                json.field("normals")?.line,
+                "normals must have as many entries as positions",
+            ));
+        }
+    }
+    if let Some(uvs) = &uvs {
+        if uvs.len() != positions.len() {
+            return Err(JsonError::new(
+                json.field("uvs")?.line,
+                "uvs must have as many entries as positions",
+            ));
+        }
+    }
+
+    let indices = json.field("indices")?;
+    let mut faces = Vec::new();
+    for face in indices.as_array()? {
+        let v = match face.as_array()? {
+            [i0, i1, i2] => [i0.as_usize()?, i1.as_usize()?, i2.as_usize()?],
+            _ => return Err(JsonError::new(face.line, "expected [i0, i1, i2]")),
+        };
+        if v.iter().any(|&i| i >= positions.len()) {
+            return Err(JsonError::new(face.line, "vertex index out of range"));
+        }
+        faces.push(Face {
+            vn: normals.as_ref().map(|_| v),
+            vt: uvs.as_ref().map(|_| v),
+            ..Face::new(v)
+        });
+    }
+    if faces.is_empty() {
+        return Err(JsonError::new(indices.line, "mesh has no faces"));
+    }
+
+    let mesh = MeshData::new(
+        positions,
+        normals.unwrap_or_default(),
+        uvs.unwrap_or_default(),
+    );
+    Ok((Arc::new(mesh), faces))
+}
+
+// ノイズ系テクスチャの共通フィールド { "seed": 0, "scale": 4 } (seed は省略可能)
+fn noise(json: &Json) -> Result<(Perlin, f64), JsonError> {
+    let seed = opt(json, "seed", Json::as_usize)?.unwrap_or(0);
+    Ok((Perlin::new(seed as u64), json.field("scale")?.as_f64()?))
+}
+
+fn texture_filter(json: &Json) -> Result<TextureFilter, JsonError> {
+    match json.as_str()? {
+        "nearest" => Ok(TextureFilter::Nearest),
+        "bilinear" => Ok(TextureFilter::Bilinear),
+        other => Err(JsonError::new(
+            json.line,
+            format!("unknown filter {:?} (expected nearest or bilinear)", other),
+        )),
+    }
+}
+
+fn wrap_mode(json: &Json) -> Result<WrapMode, JsonError> {
+    match json.as_str()? {
+        "repeat" => Ok(WrapMode::Repeat),
+        "clamp" => Ok(WrapMode::Clamp),
+        "mirror" => Ok(WrapMode::Mirror),
+        other => Err(JsonError::new(
+            json.line,
+            format!(
+                "unknown wrap mode {:?} (expected repeat, clamp or mirror)",
+                other
+            ),
+        )),
+    }
+}
+
+fn vec3(json: &Json) -> Result<Vec3, JsonError> {
+    match json.as_array()? {
+        [x, y, z] => Ok(Vec3::new(x.as_f64()?, y.as_f64()?, z.as_f64()?)),
+        a => Err(JsonError::new(
+            json.line,
+            format!("expected 3 numbers, found {}", a.len()),
+        )),
+    }
+}
+
+fn view(camera: &Json) -> Result<View, JsonError> {
+    let lookfrom = vec3(camera.field("lookfrom")?)?;
+    let lookat = vec3(camera.field("lookat")?)?;
+    let vup = opt(camera, "vup", vec3)?.unwrap_or_else(Vec3::yaxis);
+    let view = View::new(lookfrom, lookat, vup, camera.field("vfov")?.as_f64()?);
+    // 省略時は注視点にピントを合わせる
+    let focus_dist = opt(camera, "focus_distance", Json::as_f64)?.unwrap_or(view.focus_dist);
+    let (open, close) = opt(camera, "shutter", shutter)?.unwrap_or((0.0, 0.0));
+    let projection = match camera.get("projection") {
+        Some(json) => json
+            .as_str()?
+            .parse::<Projection>()
+            .map_err(|e| JsonError::new(json.line, e))?,
+        None => Projection::default(),
+    };
+    Ok(view
+        .aperture(
+            opt(camera, "aperture", Json::as_f64)?.unwrap_or(0.0),
+            focus_dist,
+        )
+        .blades(opt(camera, "blades", Json::as_usize)?.unwrap_or(0))
+        .shutter(open, close)
+        .projection(projection))
+}
+
+// { "axis": [x, y, z], "angle": deg }
+fn rotation(json: &Json) -> Result<(Vec3, f64), JsonError> {
+    Ok((
+        vec3(json.field("axis")?)?.normalize(),
+        json.field("angle")?.as_f64()?,
+    ))
+}
+
+// [open, close]
+fn shutter(json: &Json) -> Result<(f64, f64), JsonError> {
+    match json.as_array()? {
+        [t0, t1] => Ok((t0.as_f64()?, t1.as_f64()?)),
+        a => Err(JsonError::new(
+            json.line,
+            format!("expected 2 numbers, found {}", a.len()),
+        )),
+    }
+}
+
+fn color(json: &Json) -> Result<Color, JsonError> {
+    if let JsonValue::String(s) = &json.value {
+        let hex = s.trim_start_matches('#').as_bytes();
+        if hex.len() == 6 && hex.iter().all(u8::is_ascii_hexdigit) {
+            let mut bytes = [0; 6];
+            bytes.copy_from_slice(hex);
+            return Ok(Color::from_hex(&bytes));
+        }
+        return Err(JsonError::new(
+            json.line,
+            format!("invalid color {:?}, expected \"#rrggbb\"", s),
+        ));
+    }
+    vec3(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<FileScene, SceneError> {
+        FileScene::parse(src, Path::new(""))
+    }
+
+    fn error_line(src: &str) -> usize {
+        match parse(src) {
+            Err(SceneError::Json(e)) => e.line,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("accepted an invalid scene"),
+        }
+    }
+
+    const SCENE: &str = r#"{
+  "image": IMAGE,
+  "camera": { "lookfrom": [0, 0, -5], "lookat": [0, 0, 0], "vup": [0, 1, 0], "vfov": 40 },
+  "shapes": [
+    { "type": "sphere", "center": [0, 0, 0], "radius": 1,
+      "material": { "type": "lambertian", "texture": { "type": "color", "color": [0.5, 0.5, 0.5] } } }
+  ]
+}"#;
+
+    #[test]
+    fn test_image_size() {
+        let scene =
+            parse(&SCENE.replace("IMAGE", r#"{ "width": 2, "height": 3, "spp": 1 }"#)).unwrap();
+        assert_eq!((2, 3, 1), (scene.width(), scene.height(), scene.spp()));
+
+        for image in &[
+            r#"{ "width": 0 }"#,
+            r#"{ "width": 1 }"#,
+            r#"{ "height": 1 }"#,
+            r#"{ "spp": 0 }"#,
+        ] {
+            assert_eq!(2, error_line(&SCENE.replace("IMAGE", image)));
+        }
+    }
+
+    #[test]
+    fn test_density() {
+        let scene = |shape: &str| {
+            format!(
+                r#"{{
+  "materials": {{ "smoke": {{ "type": "isotropic", "texture": {{ "type": "color", "color": [1, 1, 1] }} }} }},
+  "camera": {{ "lookfrom": [0, 0, -5], "lookat": [0, 0, 0], "vup": [0, 1, 0], "vfov": 40 }},
+  "shapes": [
+    {}
+  ]
+}}"#,
+                shape
+            )
+        };
+        let lambertian =
+            r#"{ "type": "lambertian", "texture": { "type": "color", "color": [1, 1, 1] } }"#;
+        let hg = r#"{ "type": "henyey_greenstein", "texture": { "type": "color", "color": [1, 1, 1] }, "g": 0.5 }"#;
+        let sphere = |density: &str, material: &str| {
+            format!(
+                r#"{{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "density": {}, "material": {} }}"#,
+                density, material
+            )
+        };
+        assert!(parse(&scene(&sphere("0.5", "\"smoke\""))).is_ok());
+        assert!(parse(&scene(&sphere("0.5", hg))).is_ok());
+        assert_eq!(5, error_line(&scene(&sphere("0", "\"smoke\""))));
+        assert_eq!(5, error_line(&scene(&sphere("-1", hg))));
+        assert_eq!(5, error_line(&scene(&sphere("0.5", lambertian))));
+
+        let fog = |density: &str| {
+            SCENE
+                .replace("IMAGE", r#"{ "width": 2, "height": 2 }"#)
+                .replace(
+                    r#""shapes""#,
+                    &format!(r#""fog": {{ "density": {} }}, "shapes""#, density),
+                )
+        };
+        assert!(parse(&fog("0.01")).is_ok());
+        assert_eq!(4, error_line(&fog("0")));
+    }
+}